@@ -0,0 +1,284 @@
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+    marker::PhantomData,
+    str::FromStr,
+};
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::errors::AmountError;
+
+/// Number of decimal places stored exactly by [`Amount`]
+pub const DECIMAL_PLACES: usize = 4;
+const SCALE: i64 = 10_i64.pow(DECIMAL_PLACES as u32);
+
+/// Fixed-point monetary amount with 4 decimal places of exact precision.
+/// Internally the value is kept as an integer number of ten-thousandths, so no rounding
+/// happens during the processing. All arithmetic is checked - an overflow is reported
+/// to the caller instead of wrapping silently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Amount given as an integer number of ten-thousandths
+    pub fn from_units(units: i64) -> Self {
+        Amount(units)
+    }
+
+    /// Integer number of ten-thousandths
+    pub fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+}
+
+/// Amount of a deposit or withdrawal, validated to be strictly positive.
+/// Enforced when the record is deserialized, so an invalid amount never reaches the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositiveAmount(Amount);
+
+impl PositiveAmount {
+    pub fn get(self) -> Amount {
+        self.0
+    }
+}
+
+impl TryFrom<Amount> for PositiveAmount {
+    type Error = AmountError;
+
+    fn try_from(amount: Amount) -> Result<Self, Self::Error> {
+        match amount.cmp(&Amount::ZERO) {
+            Ordering::Greater => Ok(PositiveAmount(amount)),
+            Ordering::Equal => Err(AmountError::Zero(amount.to_string())),
+            Ordering::Less => Err(AmountError::Negative(amount.to_string())),
+        }
+    }
+}
+
+impl FromStr for PositiveAmount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Errors name the value as it was given in the input, not the normalized one
+        s.parse::<Amount>()?.try_into().map_err(|e| match e {
+            AmountError::Zero(_) => AmountError::Zero(s.to_string()),
+            AmountError::Negative(_) => AmountError::Negative(s.to_string()),
+            e => e,
+        })
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AmountError::Invalid(s.to_string());
+
+        // Not representable by a fixed-point type, but reported separately as they are
+        // a common result of floating point exports
+        match s.trim_start_matches(['-', '+']).to_lowercase().as_str() {
+            "nan" => return Err(AmountError::NotANumber(s.to_string())),
+            "inf" | "infinity" => return Err(AmountError::Infinite(s.to_string())),
+            _ => {}
+        }
+
+        let (negative, unsigned) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        // Trailing zeros do not change the value, so they are not counted as precision
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > DECIMAL_PLACES {
+            return Err(AmountError::TooPrecise(s.to_string()));
+        }
+
+        let out_of_range = || AmountError::OutOfRange(s.to_string());
+        let integer = match integer.trim_start_matches('0') {
+            "" => 0,
+            digits => digits.parse::<i64>().map_err(|_| out_of_range())?,
+        };
+        let fraction = format!("{fraction:0<DECIMAL_PLACES$}")
+            .parse::<i64>()
+            .map_err(|_| invalid())?;
+
+        let value = integer
+            .checked_mul(SCALE)
+            .and_then(|value| value.checked_add(fraction))
+            .ok_or_else(out_of_range)?;
+
+        Ok(Amount(if negative { -value } else { value }))
+    }
+}
+
+// Formats the amount with up to 4 decimal places, trailing zeros are trimmed
+impl Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let integer = abs / SCALE as u64;
+        let fraction = abs % SCALE as u64;
+
+        if fraction == 0 {
+            write!(f, "{sign}{integer}")
+        } else {
+            let fraction = format!("{fraction:0DECIMAL_PLACES$}");
+            write!(f, "{sign}{integer}.{}", fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Visitor parsing a value from its textual form, so no precision is lost
+/// on the way through a binary floating point type
+struct FromStrVisitor<T>(PhantomData<T>);
+
+impl<T> Visitor<'_> for FromStrVisitor<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal amount with up to 4 decimal places")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for PositiveAmount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(FromStrVisitor(PhantomData))
+    }
+}
+
+impl<'de> Deserialize<'de> for PositiveAmount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(FromStrVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("1", 10_000; "integer")]
+    #[test_case("1.5", 15_000; "one decimal")]
+    #[test_case("0.0001", 1; "smallest unit")]
+    #[test_case("-2.25", -22_500; "negative")]
+    #[test_case("+3", 30_000; "explicit plus sign")]
+    #[test_case(".5", 5_000; "no integer part")]
+    #[test_case("7.", 70_000; "no fraction part")]
+    #[test_case("1.250000", 12_500; "trailing zeros beyond precision")]
+    #[test_case("922337203685477.5807", i64::MAX; "max value")]
+    fn test_parse(input: &str, expected: i64) {
+        assert_eq!(input.parse::<Amount>().unwrap(), Amount(expected));
+    }
+
+    #[test_case("", AmountError::Invalid(String::new()); "empty")]
+    #[test_case(".", AmountError::Invalid(".".into()); "dot only")]
+    #[test_case("1,5", AmountError::Invalid("1,5".into()); "comma")]
+    #[test_case("1e5", AmountError::Invalid("1e5".into()); "exponent")]
+    #[test_case("1.23456", AmountError::TooPrecise("1.23456".into()); "too precise")]
+    #[test_case("NaN", AmountError::NotANumber("NaN".into()); "nan")]
+    #[test_case("-inf", AmountError::Infinite("-inf".into()); "negative infinity")]
+    #[test_case("Infinity", AmountError::Infinite("Infinity".into()); "infinity")]
+    #[test_case("922337203685478", AmountError::OutOfRange("922337203685478".into()); "out of range")]
+    fn test_parse_error(input: &str, expected: AmountError) {
+        assert_eq!(input.parse::<Amount>().unwrap_err(), expected);
+    }
+
+    #[test_case(10_000, "1"; "integer")]
+    #[test_case(15_000, "1.5"; "trimmed zeros")]
+    #[test_case(1, "0.0001"; "smallest unit")]
+    #[test_case(-500_000, "-50"; "negative")]
+    #[test_case(-1, "-0.0001"; "negative fraction")]
+    #[test_case(0, "0"; "zero")]
+    fn test_display(value: i64, expected: &str) {
+        assert_eq!(Amount(value).to_string(), expected);
+    }
+
+    #[test_case("0.0001", 1; "smallest unit")]
+    #[test_case("2.5", 25_000; "fraction")]
+    fn test_parse_positive(input: &str, expected: i64) {
+        assert_eq!(
+            input.parse::<PositiveAmount>().unwrap().get(),
+            Amount(expected)
+        );
+    }
+
+    #[test_case("0", AmountError::Zero("0".into()); "zero")]
+    #[test_case("-0.000", AmountError::Zero("-0.000".into()); "negative zero")]
+    #[test_case("-50", AmountError::Negative("-50".into()); "negative")]
+    #[test_case("nan", AmountError::NotANumber("nan".into()); "nan")]
+    #[test_case("inf", AmountError::Infinite("inf".into()); "infinity")]
+    fn test_parse_positive_error(input: &str, expected: AmountError) {
+        assert_eq!(input.parse::<PositiveAmount>().unwrap_err(), expected);
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let max = Amount(i64::MAX);
+        assert_eq!(max.checked_add(Amount(1)), None);
+        assert_eq!(Amount(i64::MIN).checked_sub(Amount(1)), None);
+        assert_eq!(Amount(1).checked_add(Amount(2)), Some(Amount(3)));
+        assert_eq!(Amount(1).checked_sub(Amount(2)), Some(Amount(-1)));
+    }
+}
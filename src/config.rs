@@ -1,7 +1,329 @@
-use clap::Parser;
-use std::path::PathBuf;
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufReader, Read},
+    net::SocketAddr,
+    path::PathBuf,
+};
 
+use crate::{
+    amount::Amount,
+    conversion::Rounding,
+    currency::Currency,
+    engine::{
+        ClientOrder, Currencies, DisputeWindow, EngineOptions, LockedAccountPolicy,
+        OutOfOrderPolicy, RiskRules, WithdrawalDisputePolicy, MAX_SHARDS,
+    },
+    input::{InputFormat, InputSource},
+    output::OutputFormat,
+    rejections::{RejectionFormat, Rejections},
+    ClientId,
+};
+
+/// Without a subcommand, the input files are processed in a batch and the balances are printed
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Config {
-    pub input_file_path: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Input files, processed in the given order by a single engine. Use `-` for stdin
+    #[arg(required = true)]
+    pub inputs: Vec<InputSource>,
+    /// Format of the inputs, detected from the file extension if not given (CSV for stdin)
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+    #[command(flatten)]
+    pub engine: EngineArgs,
+    /// Snapshot of the engine state to resume the processing from
+    #[arg(long)]
+    pub load_snapshot: Option<PathBuf>,
+    /// File the engine state is saved to after all inputs are processed
+    #[arg(long)]
+    pub save_snapshot: Option<PathBuf>,
+    /// Write-ahead journal of accepted records. It is replayed on startup,
+    /// and reset when a snapshot is saved
+    #[arg(long, conflicts_with = "threads")]
+    pub journal: Option<PathBuf>,
+    #[command(flatten)]
+    pub storage: StorageArgs,
+    /// Number of worker threads, records are sharded between them by the client id.
+    /// Dispute windows need the count and the clock of the whole engine, so they are
+    /// not available with more threads
+    #[arg(long, default_value_t = 1, conflicts_with_all = ["tx_spill_file", "dispute_window_transactions", "dispute_window_secs", "evict_expired", "out_of_order"], value_parser = clap::value_parser!(u8).range(1..=MAX_SHARDS as i64))]
+    pub threads: u8,
+    /// Order of the clients in the output
+    #[arg(long, value_enum, default_value_t)]
+    pub sort: ClientOrder,
+    /// Format of the client balance report
+    #[arg(long, value_enum, default_value_t)]
+    pub output_format: OutputFormat,
+    /// File the rejected records are reported to
+    #[arg(long)]
+    pub rejections: Option<PathBuf>,
+    /// Format of the rejection report, detected from the file extension if not given
+    #[arg(long, value_enum, requires = "rejections")]
+    pub rejections_format: Option<RejectionFormat>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Runs a long-lived engine accepting transactions over TCP
+    Serve(ServeConfig),
+    /// Runs a long-lived engine behind an HTTP/JSON API
+    Http(HttpConfig),
+    /// Prints a chronological ledger of a single client, with the balances after each record
+    Statement(StatementConfig),
+    /// Processes the inputs as a dry run and prints a summary, nothing is saved or written
+    Validate(ValidateConfig),
+    /// Prints the totals of the clients converted into a base currency by a table of rates
+    Convert(ConvertConfig),
+}
+
+#[derive(Args, Debug)]
+pub struct ServeConfig {
+    /// Address the server listens on
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    pub listen: SocketAddr,
+    /// Maximal number of open connections, further ones are refused
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u16).range(1..))]
+    pub max_connections: u16,
+    /// Write-ahead journal of accepted records, replayed on startup
+    #[arg(long)]
+    pub journal: Option<PathBuf>,
+    #[command(flatten)]
+    pub engine: EngineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct HttpConfig {
+    /// Address the HTTP server listens on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+    /// Number of worker threads, each handles one request at a time
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    pub workers: u16,
+    /// Write-ahead journal of accepted records, replayed on startup
+    #[arg(long)]
+    pub journal: Option<PathBuf>,
+    #[command(flatten)]
+    pub engine: EngineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct StatementConfig {
+    /// Client the statement is printed for
+    #[arg(long)]
+    pub client: ClientId,
+    /// Input files, processed in the given order by a single engine. Use `-` for stdin
+    #[arg(required = true)]
+    pub inputs: Vec<InputSource>,
+    /// Format of the inputs, detected from the file extension if not given (CSV for stdin)
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+    /// Snapshot with the state at the start of the statement period
+    #[arg(long)]
+    pub load_snapshot: Option<PathBuf>,
+    #[command(flatten)]
+    pub engine: EngineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ValidateConfig {
+    /// Input files, processed in the given order by a single engine. Use `-` for stdin
+    #[arg(required = true)]
+    pub inputs: Vec<InputSource>,
+    /// Format of the inputs, detected from the file extension if not given (CSV for stdin)
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+    /// Snapshot of the engine state the inputs are validated against, it is not modified
+    #[arg(long)]
+    pub load_snapshot: Option<PathBuf>,
+    #[command(flatten)]
+    pub storage: StorageArgs,
+    /// CSV with the expected balances in the output format, the resulting balances
+    /// are compared with it
+    #[arg(long)]
+    pub expected: Option<PathBuf>,
+    #[command(flatten)]
+    pub engine: EngineArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertConfig {
+    /// CSV with a `currency,rate` header, each rate is the value of one unit
+    /// of the currency in the base currency
+    #[arg(long)]
+    pub rates: PathBuf,
+    /// Currency the totals are converted into
+    #[arg(long, default_value = "EUR")]
+    pub base_currency: Currency,
+    /// How the converted totals are rounded to 4 decimal places
+    #[arg(long, value_enum, default_value_t)]
+    pub rounding: Rounding,
+    /// Input files, processed in the given order by a single engine. Use `-` for stdin.
+    /// Without inputs, the balances of the snapshot are converted
+    pub inputs: Vec<InputSource>,
+    /// Format of the inputs, detected from the file extension if not given (CSV for stdin)
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+    /// Snapshot of the engine state to convert, the inputs are processed on top of it
+    #[arg(long)]
+    pub load_snapshot: Option<PathBuf>,
+    #[command(flatten)]
+    pub storage: StorageArgs,
+    #[command(flatten)]
+    pub engine: EngineArgs,
+}
+
+/// Options of the engine, shared by all modes
+#[derive(Args, Debug)]
+pub struct EngineArgs {
+    /// How disputes referring to a withdrawal are handled
+    #[arg(long, value_enum, default_value_t)]
+    pub withdrawal_dispute_policy: WithdrawalDisputePolicy,
+    /// Which operations are still allowed on an account locked by a chargeback
+    #[arg(long, value_enum, default_value_t)]
+    pub locked_account_policy: LockedAccountPolicy,
+    /// Deposits and withdrawals can be disputed only until this many other ones are committed
+    #[arg(long, conflicts_with = "dispute_window_secs")]
+    pub dispute_window_transactions: Option<u64>,
+    /// Deposits and withdrawals can be disputed only for this many seconds,
+    /// by the timestamps of the records
+    #[arg(long)]
+    pub dispute_window_secs: Option<u64>,
+    /// Drops transactions out of the dispute window from the store, unless they are
+    /// under dispute. Ids of the dropped transactions are no longer checked for reuse
+    #[arg(long)]
+    pub evict_expired: bool,
+    /// How records with a timestamp older than the latest accepted one are handled
+    #[arg(long, value_enum, default_value_t)]
+    pub out_of_order: OutOfOrderPolicy,
+    /// Number of records held back to reorder each input by the timestamps
+    #[arg(long, default_value_t = 10_000)]
+    pub resequence_buffer: usize,
+    /// Currencies of the accepted records, records in other currencies are rejected
+    #[arg(long, value_delimiter = ',', default_value = "EUR,USD,GBP")]
+    pub currencies: Vec<Currency>,
+    /// Currency of the records without a `currency` column, it is always accepted
+    #[arg(long, default_value = "EUR")]
+    pub default_currency: Currency,
+    /// CSV with a `client,currency,limit` header, the available balance of a client
+    /// may go down to minus its limit. Rows without a currency are in the default one
+    #[arg(long)]
+    pub credit_limits: Option<PathBuf>,
+    /// JSON file with the risk rules the records are checked against before they are applied
+    #[arg(long)]
+    pub risk_rules: Option<PathBuf>,
+}
+
+/// Where the committed transactions are kept during a batch run
+#[derive(Args, Debug)]
+pub struct StorageArgs {
+    /// File the committed transactions are spilled to, so only a part of them is kept
+    /// in memory. The file is sparse and it is removed once the processing finishes
+    #[arg(long)]
+    pub tx_spill_file: Option<PathBuf>,
+    /// Number of the most recently used (read or written) transactions kept in memory
+    /// when they are spilled to a file
+    #[arg(long, default_value_t = 1_000_000, requires = "tx_spill_file")]
+    pub tx_cache_size: usize,
+}
+
+/// Credit limit of a client, a row of the credit limits file
+#[derive(Deserialize)]
+struct CreditLimitRecord {
+    client: ClientId,
+    #[serde(default)]
+    currency: Option<Currency>,
+    limit: Amount,
+}
+
+/// Reads the credit limits, each client can have one limit in each currency
+fn read_credit_limits(
+    reader: impl Read,
+    default_currency: Currency,
+) -> anyhow::Result<BTreeMap<(ClientId, Currency), Amount>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut limits = BTreeMap::new();
+    for record in reader.deserialize() {
+        let CreditLimitRecord {
+            client,
+            currency,
+            limit,
+        } = record?;
+        let currency = currency.unwrap_or(default_currency);
+        if limit < Amount::ZERO {
+            anyhow::bail!("Credit limit of client {client} in {currency} is negative");
+        }
+        if limits.insert((client, currency), limit).is_some() {
+            anyhow::bail!("Credit limit of client {client} in {currency} is given more than once");
+        }
+    }
+    Ok(limits)
+}
+
+impl EngineArgs {
+    pub fn options(&self) -> anyhow::Result<EngineOptions> {
+        let credit_limits = match &self.credit_limits {
+            Some(path) => {
+                let file = File::open(path)
+                    .with_context(|| format!("Failed to open credit limits {}", path.display()))?;
+                read_credit_limits(BufReader::new(file), self.default_currency)
+                    .with_context(|| format!("Failed to read credit limits {}", path.display()))?
+            }
+            None => BTreeMap::new(),
+        };
+        let risk_rules = match &self.risk_rules {
+            Some(path) => {
+                let file = File::open(path)
+                    .with_context(|| format!("Failed to open risk rules {}", path.display()))?;
+                serde_json::from_reader(BufReader::new(file))
+                    .with_context(|| format!("Failed to read risk rules {}", path.display()))?
+            }
+            None => RiskRules::default(),
+        };
+        Ok(EngineOptions {
+            withdrawal_dispute_policy: self.withdrawal_dispute_policy,
+            locked_account_policy: self.locked_account_policy,
+            dispute_window: self
+                .dispute_window_transactions
+                .map(DisputeWindow::Transactions)
+                .or(self.dispute_window_secs.map(DisputeWindow::Seconds)),
+            evict_expired: self.evict_expired,
+            out_of_order_policy: self.out_of_order,
+            resequence_buffer: self.resequence_buffer,
+            currencies: Currencies {
+                accepted: self
+                    .currencies
+                    .iter()
+                    .copied()
+                    .chain([self.default_currency])
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+                default: self.default_currency,
+            },
+            credit_limits,
+            risk_rules,
+        })
+    }
+}
+
+impl Config {
+    pub fn rejections(&self) -> anyhow::Result<Rejections> {
+        match &self.rejections {
+            Some(path) => {
+                let format = self
+                    .rejections_format
+                    .unwrap_or_else(|| RejectionFormat::from_path(path));
+                Rejections::create(path, format)
+            }
+            None => Ok(Rejections::default()),
+        }
+    }
 }
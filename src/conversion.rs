@@ -0,0 +1,304 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    amount::Amount, currency::Currency, engine::TxEngine, errors::ConversionError, ClientId,
+};
+
+/// Number of decimal places of an exchange rate
+pub const RATE_DECIMAL_PLACES: usize = 8;
+const RATE_SCALE: i128 = 10_i128.pow(RATE_DECIMAL_PLACES as u32);
+
+/// Exchange rate - the value of one unit of a currency in the base currency.
+/// Kept as an integer number of hundred-millionths, so rates are exact like amounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Rate(i64);
+
+impl Rate {
+    pub const ONE: Rate = Rate(RATE_SCALE as i64);
+}
+
+impl FromStr for Rate {
+    type Err = ConversionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ConversionError::InvalidRate(s.to_string());
+
+        let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > RATE_DECIMAL_PLACES {
+            return Err(invalid());
+        }
+
+        let integer = match integer.trim_start_matches('0') {
+            "" => 0,
+            digits => digits.parse::<i64>().map_err(|_| invalid())?,
+        };
+        let fraction = format!("{fraction:0<RATE_DECIMAL_PLACES$}")
+            .parse::<i64>()
+            .map_err(|_| invalid())?;
+        let value = integer
+            .checked_mul(RATE_SCALE as i64)
+            .and_then(|value| value.checked_add(fraction))
+            .ok_or_else(invalid)?;
+        // A zero rate would hide the holdings in the currency instead of converting them
+        if value == 0 {
+            return Err(invalid());
+        }
+        Ok(Rate(value))
+    }
+}
+
+// Formats the rate with up to 8 decimal places, trailing zeros are trimmed
+impl Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let integer = self.0 / RATE_SCALE as i64;
+        let fraction = self.0 % RATE_SCALE as i64;
+        if fraction == 0 {
+            write!(f, "{integer}")
+        } else {
+            let fraction = format!("{fraction:0RATE_DECIMAL_PLACES$}");
+            write!(f, "{integer}.{}", fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let rate = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        rate.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// How a converted amount is rounded to the 4 decimal places of an amount
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Rounding {
+    /// To the nearest value, ties to the even last digit (banker's rounding)
+    #[default]
+    HalfEven,
+    /// To the nearest value, ties away from zero
+    HalfUp,
+    /// Towards zero, the magnitude of the converted amount never exceeds the exact one
+    Down,
+}
+
+impl Rounding {
+    /// Divides the value by the divisor, rounding the quotient by the mode
+    fn divide(self, value: i128, divisor: i128) -> i128 {
+        let (quotient, remainder) = (value / divisor, value % divisor);
+        let away = match self {
+            Rounding::Down => false,
+            Rounding::HalfUp => remainder.abs() * 2 >= divisor,
+            Rounding::HalfEven => match (remainder.abs() * 2).cmp(&divisor) {
+                std::cmp::Ordering::Less => false,
+                std::cmp::Ordering::Equal => quotient % 2 != 0,
+                std::cmp::Ordering::Greater => true,
+            },
+        };
+        match (away, value < 0) {
+            (false, _) => quotient,
+            (true, false) => quotient + 1,
+            (true, true) => quotient - 1,
+        }
+    }
+}
+
+/// Exchange rates of the currencies into the base currency, the base currency itself
+/// is always converted at 1
+#[derive(Debug, Clone)]
+pub struct Rates {
+    base: Currency,
+    rates: BTreeMap<Currency, Rate>,
+}
+
+#[derive(Deserialize)]
+struct RateRecord {
+    currency: Currency,
+    rate: Rate,
+}
+
+impl Rates {
+    pub fn get(&self, currency: Currency) -> Option<Rate> {
+        if currency == self.base {
+            return Some(Rate::ONE);
+        }
+        self.rates.get(&currency).copied()
+    }
+
+    /// Converts the amount into the base currency, rounded to 4 decimal places
+    pub fn convert(
+        &self,
+        amount: Amount,
+        currency: Currency,
+        rounding: Rounding,
+    ) -> Result<(Rate, Amount), ConversionError> {
+        let rate = self
+            .get(currency)
+            .ok_or(ConversionError::MissingRate(currency))?;
+        let exact = i128::from(amount.units()) * i128::from(rate.0);
+        let units = rounding.divide(exact, RATE_SCALE);
+        let converted = i64::try_from(units).map_err(|_| ConversionError::Overflow)?;
+        Ok((rate, Amount::from_units(converted)))
+    }
+}
+
+/// Reads the rates into the base currency from a CSV with a `currency,rate` header.
+/// Each currency can be listed once, the base currency only with the rate of 1.
+pub fn read_rates(reader: impl Read, base: Currency) -> anyhow::Result<Rates> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut rates = BTreeMap::new();
+    for record in reader.deserialize() {
+        let RateRecord { currency, rate } = record?;
+        if currency == base && rate != Rate::ONE {
+            return Err(ConversionError::BaseRate(base, rate).into());
+        }
+        if rates.insert(currency, rate).is_some() {
+            return Err(ConversionError::DuplicateRate(currency).into());
+        }
+    }
+    rates.remove(&base);
+    Ok(Rates { base, rates })
+}
+
+/// Row of the conversion report. Each client has a row for every currency it holds,
+/// with the native total and the total converted into the base currency, followed by
+/// a row with the sum of the converted totals and no currency.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConvertedBalance {
+    pub client: ClientId,
+    pub currency: Option<Currency>,
+    pub total: Option<Amount>,
+    pub rate: Option<Rate>,
+    pub converted_total: Amount,
+}
+
+/// Converts the total of every client in every currency into the base currency.
+/// Each converted total is rounded on its own, the client sum adds up the rounded totals,
+/// so the rows of a client always add up to its sum. Fails on a currency without a rate.
+pub fn convert_balances(
+    engine: &TxEngine,
+    rates: &Rates,
+    rounding: Rounding,
+) -> Result<Vec<ConvertedBalance>, ConversionError> {
+    let mut rows = Vec::new();
+    for client in engine.get_clients() {
+        let mut sum = Amount::ZERO;
+        for (currency, balance) in client.balances() {
+            let (rate, converted) = rates.convert(balance.total(), currency, rounding)?;
+            sum = sum
+                .checked_add(converted)
+                .ok_or(ConversionError::Overflow)?;
+            rows.push(ConvertedBalance {
+                client: client.id(),
+                currency: Some(currency),
+                total: Some(balance.total()),
+                rate: Some(rate),
+                converted_total: converted,
+            });
+        }
+        rows.push(ConvertedBalance {
+            client: client.id(),
+            currency: None,
+            total: None,
+            rate: None,
+            converted_total: sum,
+        });
+    }
+    Ok(rows)
+}
+
+/// Writes the report as CSV with a `client,currency,total,rate,converted_total` header
+pub fn write_conversion(rows: &[ConvertedBalance], writer: impl Write) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("1", 100_000_000; "integer")]
+    #[test_case("1.08453", 108_453_000; "fraction")]
+    #[test_case("0.00000001", 1; "smallest unit")]
+    #[test_case("0.5000000000", 50_000_000; "trailing zeros beyond precision")]
+    fn test_parse_rate(input: &str, expected: i64) {
+        assert_eq!(input.parse::<Rate>().unwrap(), Rate(expected));
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("0"; "zero")]
+    #[test_case("-1.1"; "negative")]
+    #[test_case("1.000000001"; "too precise")]
+    #[test_case("1e3"; "exponent")]
+    fn test_parse_rate_error(input: &str) {
+        assert_eq!(
+            input.parse::<Rate>().unwrap_err(),
+            ConversionError::InvalidRate(input.to_string())
+        );
+    }
+
+    #[test_case(Rounding::HalfEven, "0.00005", "0"; "half even tie down")]
+    #[test_case(Rounding::HalfEven, "0.00015", "0.0002"; "half even tie up")]
+    #[test_case(Rounding::HalfEven, "-0.00015", "-0.0002"; "half even negative tie")]
+    #[test_case(Rounding::HalfEven, "0.000051", "0.0001"; "half even above tie")]
+    #[test_case(Rounding::HalfUp, "0.00005", "0.0001"; "half up tie")]
+    #[test_case(Rounding::HalfUp, "-0.00005", "-0.0001"; "half up negative tie")]
+    #[test_case(Rounding::HalfUp, "0.000049", "0"; "half up below tie")]
+    #[test_case(Rounding::Down, "0.00009999", "0"; "down")]
+    #[test_case(Rounding::Down, "-0.00019", "-0.0001"; "down negative")]
+    fn test_rounding(rounding: Rounding, rate: &str, expected: &str) {
+        let rates = Rates {
+            base: Currency::EUR,
+            rates: BTreeMap::from([(Currency::USD, rate.trim_start_matches('-').parse().unwrap())]),
+        };
+        // One unit converted at the rate gives the rate itself, rounded to 4 decimal places
+        let amount = if rate.starts_with('-') { "-1" } else { "1" };
+        let (_, converted) = rates
+            .convert(amount.parse().unwrap(), Currency::USD, rounding)
+            .unwrap();
+        assert_eq!(converted, expected.parse().unwrap());
+    }
+
+    #[test]
+    fn test_base_currency_is_not_converted() {
+        let rates = read_rates("currency,rate\nUSD,0.9\n".as_bytes(), Currency::EUR).unwrap();
+        assert_eq!(rates.get(Currency::EUR), Some(Rate::ONE));
+        assert_eq!(rates.get(Currency::GBP), None);
+    }
+}
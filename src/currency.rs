@@ -0,0 +1,103 @@
+use std::{
+    fmt::{self, Debug, Display},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::errors::CurrencyError;
+
+/// ISO 4217 currency code, three ASCII letters kept in upper case.
+/// Any well-formed code is parsed, the engine decides which currencies it accepts.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const EUR: Currency = Currency(*b"EUR");
+    pub const USD: Currency = Currency(*b"USD");
+    pub const GBP: Currency = Currency(*b"GBP");
+
+    /// Currency from the bytes of its code, `None` if they are not a valid code
+    pub fn from_bytes(bytes: [u8; 3]) -> Option<Self> {
+        bytes
+            .iter()
+            .all(u8::is_ascii_uppercase)
+            .then_some(Currency(bytes))
+    }
+
+    pub fn as_bytes(&self) -> [u8; 3] {
+        self.0
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("Currency code is ASCII")
+    }
+}
+
+impl FromStr for Currency {
+    type Err = CurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <[u8; 3]>::try_from(s.to_ascii_uppercase().as_bytes())
+            .ok()
+            .and_then(Currency::from_bytes)
+            .ok_or_else(|| CurrencyError::Invalid(s.to_string()))
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let code = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("EUR", Currency::EUR; "upper case")]
+    #[test_case("usd", Currency::USD; "lower case")]
+    #[test_case("Gbp", Currency::GBP; "mixed case")]
+    fn test_parse(input: &str, expected: Currency) {
+        assert_eq!(input.parse::<Currency>().unwrap(), expected);
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("EU"; "too short")]
+    #[test_case("EURO"; "too long")]
+    #[test_case("E1R"; "digit")]
+    #[test_case("ÉUR"; "non-ascii")]
+    fn test_parse_error(input: &str) {
+        assert_eq!(
+            input.parse::<Currency>().unwrap_err(),
+            CurrencyError::Invalid(input.to_string())
+        );
+    }
+}
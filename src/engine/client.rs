@@ -1,91 +1,382 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize};
 
-use crate::{errors::ProcessingError, ClientId, Float};
+use super::{
+    ledger::{Account, Posting},
+    LockedAccountPolicy,
+};
+use crate::{amount::Amount, currency::Currency, errors::ProcessingError, ClientId};
 
 type ProcessingResult<T> = Result<T, ProcessingError>;
 
-#[derive(Debug, Serialize)]
+/// Balances of a client in a currency are the balances of its available and held
+/// ledger accounts in that currency, changed only by postings. The total is derived from them.
+/// The lock applies to the client in all currencies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "ClientRecord", try_from = "ClientRecord")]
 pub struct Client {
-    #[serde(rename = "client")]
     id: ClientId,
-    #[serde(serialize_with = "serialize_float")]
-    available: Float,
-    #[serde(serialize_with = "serialize_float")]
-    held: Float,
-    #[serde(serialize_with = "serialize_float")]
-    total: Float,
+    balances: BTreeMap<Currency, Balance>,
     locked: bool,
+    /// Credit limits set by limit records, they take precedence over the configured ones
+    credit_limits: BTreeMap<Currency, Amount>,
 }
 
-fn serialize_float<S>(value: &f64, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    // Format the float to 4 decimal places
-    let formatted = format!("{:.4}", value);
-    // Trim trailing zeros and the decimal point if it's not needed
-    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
-    serializer.serialize_str(trimmed)
+/// Available and held balance of a client in a single currency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance {
+    available: Amount,
+    held: Amount,
+}
+
+impl Balance {
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    pub fn total(&self) -> Amount {
+        self.available
+            .checked_add(self.held)
+            .expect("Total is checked by every posting")
+    }
+}
+
+/// Balance of a client in one currency, the row of the balance report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClientBalance {
+    client: ClientId,
+    currency: Currency,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    /// Available balance is below zero, by an overdraft or by a dispute
+    overdrawn: bool,
+}
+
+impl ClientBalance {
+    pub fn id(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    pub fn total(&self) -> Amount {
+        self.total
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_overdrawn(&self) -> bool {
+        self.overdrawn
+    }
+}
+
+/// Serialized form of a client, with the derived totals
+#[derive(Serialize, Deserialize)]
+struct ClientRecord {
+    client: ClientId,
+    balances: BTreeMap<Currency, BalanceRecord>,
+    locked: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    credit_limits: BTreeMap<Currency, Amount>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BalanceRecord {
+    available: Amount,
+    held: Amount,
+    total: Amount,
+}
+
+impl From<Client> for ClientRecord {
+    fn from(client: Client) -> Self {
+        let balances = client
+            .balances
+            .iter()
+            .map(|(currency, balance)| {
+                let record = BalanceRecord {
+                    available: balance.available,
+                    held: balance.held,
+                    total: balance.total(),
+                };
+                (*currency, record)
+            })
+            .collect();
+        Self {
+            client: client.id,
+            balances,
+            locked: client.locked,
+            credit_limits: client.credit_limits,
+        }
+    }
+}
+
+impl TryFrom<ClientRecord> for Client {
+    type Error = String;
+
+    fn try_from(record: ClientRecord) -> Result<Self, Self::Error> {
+        let mut balances = BTreeMap::new();
+        for (currency, balance) in record.balances {
+            if balance.available.checked_add(balance.held) != Some(balance.total) {
+                return Err(format!(
+                    "total of client {} in {currency} does not match its available and held balances",
+                    record.client
+                ));
+            }
+            let balance = Balance {
+                available: balance.available,
+                held: balance.held,
+            };
+            balances.insert(currency, balance);
+        }
+        if record
+            .credit_limits
+            .values()
+            .any(|limit| *limit < Amount::ZERO)
+        {
+            return Err(format!(
+                "credit limit of client {} is negative",
+                record.client
+            ));
+        }
+        Ok(Client {
+            id: record.client,
+            balances,
+            locked: record.locked,
+            credit_limits: record.credit_limits,
+        })
+    }
+}
+
+/// Maps the result of a checked arithmetic operation to a processing error
+fn checked(value: Option<Amount>) -> ProcessingResult<Amount> {
+    value.ok_or(ProcessingError::AmountOverflow)
 }
 
 impl Client {
     pub fn new(id: ClientId) -> Self {
         Self {
             id,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
+            balances: BTreeMap::new(),
             locked: false,
+            credit_limits: BTreeMap::new(),
         }
     }
 
-    pub fn deposit(&mut self, amount: Float) -> ProcessingResult<()> {
+    // Every operation is a single posting between the accounts of the client
+    // and the external accounts, the applied posting is returned to be recorded
+    // in the ledger.
+
+    pub fn deposit(&mut self, amount: Amount, currency: Currency) -> ProcessingResult<Posting> {
         self.lockable_operation(|client| {
-            client.available += amount;
-            client.total += amount;
-            Ok(())
+            client.post(Posting::new(
+                Account::Settlement,
+                Account::Available(client.id),
+                amount,
+                currency,
+            ))
         })
     }
 
-    pub fn withdraw(&mut self, amount: Float) -> ProcessingResult<()> {
+    /// Withdraws the amount, the available balance may go down to minus the credit limit
+    pub fn withdraw(
+        &mut self,
+        amount: Amount,
+        currency: Currency,
+        credit_limit: Amount,
+    ) -> ProcessingResult<Posting> {
         self.lockable_operation(|client| {
-            if client.available < amount {
-                return Err(ProcessingError::InsufficientFunds);
+            let spendable = checked(client.balance(currency).available.checked_add(credit_limit))?;
+            if spendable < amount {
+                return Err(if credit_limit == Amount::ZERO {
+                    ProcessingError::InsufficientFunds
+                } else {
+                    ProcessingError::CreditLimitExceeded(credit_limit)
+                });
             }
-            client.available -= amount;
-            client.total -= amount;
-            Ok(())
+            client.post(Posting::new(
+                Account::Available(client.id),
+                Account::Settlement,
+                amount,
+                currency,
+            ))
         })
     }
 
-    pub fn dispute(&mut self, amount: Float) -> ProcessingResult<()> {
+    pub fn dispute(&mut self, amount: Amount, currency: Currency) -> ProcessingResult<Posting> {
         self.lockable_operation(|client| {
-            client.available -= amount;
-            client.held += amount;
-            Ok(())
+            client.post(Posting::new(
+                Account::Available(client.id),
+                Account::Held(client.id),
+                amount,
+                currency,
+            ))
         })
     }
 
-    pub fn resolve(&mut self, amount: Float) -> ProcessingResult<()> {
-        self.lockable_operation(|client| {
+    pub fn resolve(
+        &mut self,
+        amount: Amount,
+        currency: Currency,
+        policy: LockedAccountPolicy,
+    ) -> ProcessingResult<Posting> {
+        self.settlement_operation(policy, |client| {
             // held won't be less than 0, because it's only added by dispute
-            client.held -= amount;
-            client.available += amount;
-            Ok(())
+            client.post(Posting::new(
+                Account::Held(client.id),
+                Account::Available(client.id),
+                amount,
+                currency,
+            ))
+        })
+    }
+
+    pub fn charge_back(
+        &mut self,
+        amount: Amount,
+        currency: Currency,
+        policy: LockedAccountPolicy,
+    ) -> ProcessingResult<Posting> {
+        self.settlement_operation(policy, |client| {
+            let posting = client.post(Posting::new(
+                Account::Held(client.id),
+                Account::Chargebacks,
+                amount,
+                currency,
+            ))?;
+            client.locked = true;
+            Ok(posting)
         })
     }
 
-    pub fn charge_back(&mut self, amount: Float) -> ProcessingResult<()> {
+    // Reversal of a withdrawal - the withdrawn funds are claimed back by the client.
+    // During the dispute they are held, on chargeback they are credited back to available.
+
+    pub fn dispute_withdrawal(
+        &mut self,
+        amount: Amount,
+        currency: Currency,
+    ) -> ProcessingResult<Posting> {
         self.lockable_operation(|client| {
-            client.held -= amount;
-            client.total -= amount;
+            client.post(Posting::new(
+                Account::Chargebacks,
+                Account::Held(client.id),
+                amount,
+                currency,
+            ))
+        })
+    }
+
+    pub fn resolve_withdrawal(
+        &mut self,
+        amount: Amount,
+        currency: Currency,
+        policy: LockedAccountPolicy,
+    ) -> ProcessingResult<Posting> {
+        self.settlement_operation(policy, |client| {
+            client.post(Posting::new(
+                Account::Held(client.id),
+                Account::Chargebacks,
+                amount,
+                currency,
+            ))
+        })
+    }
+
+    pub fn charge_back_withdrawal(
+        &mut self,
+        amount: Amount,
+        currency: Currency,
+        policy: LockedAccountPolicy,
+    ) -> ProcessingResult<Posting> {
+        self.settlement_operation(policy, |client| {
+            let posting = client.post(Posting::new(
+                Account::Held(client.id),
+                Account::Available(client.id),
+                amount,
+                currency,
+            ))?;
             client.locked = true;
-            Ok(())
+            Ok(posting)
         })
     }
 
+    /// Applies the sides of the posting on the accounts of the client. All new balances
+    /// are calculated before any of them is assigned, so a failed posting never leaves
+    /// the client partially updated.
+    fn post(&mut self, posting: Posting) -> ProcessingResult<Posting> {
+        let Balance {
+            mut available,
+            mut held,
+        } = self.balance(posting.currency);
+        for (account, credit) in [(posting.from, false), (posting.to, true)] {
+            let balance = match account {
+                Account::Available(id) if id == self.id => &mut available,
+                Account::Held(id) if id == self.id => &mut held,
+                _ => continue,
+            };
+            *balance = checked(if credit {
+                balance.checked_add(posting.amount)
+            } else {
+                balance.checked_sub(posting.amount)
+            })?;
+        }
+        // The derived total has to be representable too
+        checked(available.checked_add(held))?;
+
+        self.balances
+            .insert(posting.currency, Balance { available, held });
+        Ok(posting)
+    }
+
+    /// Locks the account the same way as a chargeback, used by the risk rules
+    pub fn lock(&mut self) {
+        self.locked = true;
+    }
+
+    /// Lifts the lock set by a chargeback
+    pub fn unlock(&mut self) -> ProcessingResult<()> {
+        if !self.locked {
+            return Err(ProcessingError::ClientNotLocked);
+        }
+        self.locked = false;
+        Ok(())
+    }
+
+    /// Sets the credit limit in the currency, allowed on a locked account too.
+    /// Lowering the limit doesn't change the balances, an account over the new limit
+    /// stays overdrawn until it's paid back.
+    pub fn set_credit_limit(&mut self, currency: Currency, limit: Amount) {
+        self.credit_limits.insert(currency, limit);
+    }
+
+    /// Credit limit set by a limit record, `None` if the configured one applies
+    pub fn credit_limit(&self, currency: Currency) -> Option<Amount> {
+        self.credit_limits.get(&currency).copied()
+    }
+
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
     /// Wraps the operation in a lock check. This is trivial case, but in case of changes
     /// it will be easier to maintain if the lock check is in one place
     fn lockable_operation<T>(
@@ -99,38 +390,97 @@ impl Client {
         }
     }
 
-    #[cfg(test)]
-    pub fn available(&self) -> Float {
-        self.available
+    /// Settles a dispute that is already open, which may be allowed on a locked account
+    /// depending on the policy
+    fn settlement_operation<T>(
+        &mut self,
+        policy: LockedAccountPolicy,
+        op: impl FnOnce(&mut Self) -> ProcessingResult<T>,
+    ) -> ProcessingResult<T> {
+        match policy {
+            LockedAccountPolicy::RejectAll => self.lockable_operation(op),
+            LockedAccountPolicy::SettleDisputes => op(self),
+        }
     }
 
-    #[cfg(test)]
-    pub fn held(&self) -> Float {
-        self.held
+    /// Balance in the currency, zero if the client has never used it
+    pub fn balance(&self, currency: Currency) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
     }
 
-    #[cfg(test)]
-    pub fn total(&self) -> Float {
-        self.total
+    /// Balances in the currencies the client has used, ordered by the currency
+    pub fn balances(&self) -> impl Iterator<Item = (Currency, Balance)> + '_ {
+        self.balances
+            .iter()
+            .map(|(currency, balance)| (*currency, *balance))
+    }
+
+    /// Rows of the balance report, one for each currency the client has used
+    pub fn balance_rows(&self) -> impl Iterator<Item = ClientBalance> + '_ {
+        self.balances().map(|(currency, balance)| ClientBalance {
+            client: self.id,
+            currency,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total(),
+            locked: self.locked,
+            overdrawn: balance.available < Amount::ZERO,
+        })
     }
 
-    #[cfg(test)]
     pub fn is_locked(&self) -> bool {
         self.locked
     }
 }
 
+/// Order of the client balances returned by the engine, ties are always broken
+/// by the client id and the currency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClientOrder {
+    #[default]
+    Id,
+    Total,
+    Available,
+}
+
+/// Ordered by the client id, so the iteration order is stable between runs
 #[derive(Default)]
 pub struct ClientStore {
-    clients: HashMap<ClientId, Client>,
+    clients: BTreeMap<ClientId, Client>,
 }
 
 impl ClientStore {
-    pub fn get_client_mut(&mut self, id: ClientId) -> &mut Client {
-        self.clients.entry(id).or_insert_with(|| Client::new(id))
+    pub fn into_iter(self) -> impl Iterator<Item = Client> {
+        self.clients.into_values()
     }
 
-    pub fn get_clients(&self) -> impl Iterator<Item = &Client> {
+    pub fn get_client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    /// Inserts or replaces the client, used to commit its updated state
+    pub fn put(&mut self, client: Client) {
+        self.clients.insert(client.id, client);
+    }
+
+    /// Iterates over the clients ordered by their id
+    pub fn iter(&self) -> impl Iterator<Item = &Client> {
         self.clients.values()
     }
+
+    /// Balances of all clients, one for each client and currency
+    pub fn get_balances(&self, order: ClientOrder) -> impl Iterator<Item = ClientBalance> {
+        let mut balances: Vec<_> = self
+            .clients
+            .values()
+            .flat_map(Client::balance_rows)
+            .collect();
+        // Stable sort keeps the id and currency order for equal keys
+        match order {
+            ClientOrder::Id => {}
+            ClientOrder::Total => balances.sort_by_key(|balance| balance.total),
+            ClientOrder::Available => balances.sort_by_key(|balance| balance.available),
+        }
+        balances.into_iter()
+    }
 }
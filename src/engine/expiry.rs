@@ -0,0 +1,34 @@
+use super::{DisputeWindow, Transaction, TxEngine};
+use crate::{errors::ProcessingError, timestamp::Timestamp};
+
+/// Number of committed deposits and withdrawals between the eviction passes
+pub const EVICTION_INTERVAL: u64 = 4096;
+
+impl DisputeWindow {
+    /// Whether the transaction can no longer be disputed. `committed` is the number
+    /// of deposits and withdrawals committed by the engine, `now` the time of the dispute.
+    pub fn is_expired(&self, tx: &Transaction, committed: u64, now: Option<Timestamp>) -> bool {
+        match *self {
+            DisputeWindow::Transactions(window) => {
+                committed.saturating_sub(tx.sequence() + 1) > window
+            }
+            DisputeWindow::Seconds(window) => match (tx.timestamp(), now) {
+                (Some(timestamp), Some(now)) => now.secs_since(timestamp) > window as i64,
+                _ => false,
+            },
+        }
+    }
+}
+
+impl TxEngine {
+    /// Drops the expired transactions that are not under dispute from the store,
+    /// returns their number. Without a dispute window nothing expires.
+    pub fn evict_expired(&mut self) -> Result<usize, ProcessingError> {
+        let Some(window) = self.options.dispute_window else {
+            return Ok(0);
+        };
+        let (committed, now) = (self.committed, self.clock);
+        self.committed_txs
+            .evict_expired(|tx| window.is_expired(tx, committed, now))
+    }
+}
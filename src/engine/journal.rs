@@ -0,0 +1,194 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+
+use super::TxEngine;
+use crate::{
+    errors::{JournalError, ProcessingError},
+    transaction_record::TransactionRecord,
+};
+
+/// Size of the entry header - payload length and its CRC32 checksum, both little endian u32
+const HEADER_SIZE: usize = 8;
+/// Largest payload of an entry, a larger length in a header can only be a corruption
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+
+/// Append-only write-ahead journal of transaction records, each one is appended once
+/// it's validated and before it's committed.
+/// Each entry is a header followed by the record serialized to JSON. An entry that was not
+/// fully written (e.g. the process was killed in the middle of a write) is detected by
+/// its length or checksum and truncated during the recovery. Only the last entry can be
+/// torn this way - an invalid entry followed by more data is a corruption, and the recovery
+/// fails instead of dropping the valid entries after it.
+/// Entries are not synced to disk one by one - the journal survives a crash of the process,
+/// the data is synced to disk when the processing finishes.
+pub struct Journal {
+    file: File,
+}
+
+/// Outcome of the journal recovery
+#[derive(Debug, Default)]
+pub struct RecoverySummary {
+    pub replayed: u64,
+    pub rejected: Vec<RejectedEntry>,
+    pub truncated_bytes: u64,
+}
+
+/// Journaled record rejected during the replay
+#[derive(Debug)]
+pub struct RejectedEntry {
+    /// Offset of the entry in the journal
+    pub offset: u64,
+    pub error: ProcessingError,
+}
+
+enum Entry {
+    Complete(Vec<u8>),
+    /// The entry ends at or beyond the end of the input, but it is incomplete or its checksum
+    /// doesn't match. `size` is the size the entry would have by its header.
+    Torn {
+        size: u64,
+    },
+    /// The header gives a length no entry can have
+    Corrupted,
+    End,
+}
+
+impl Journal {
+    /// Opens the journal (creating it if needed) and replays all its records into the engine.
+    /// A torn entry at the end of the journal is truncated, so new entries can be appended.
+    /// Fails on a corrupted entry in the middle of the journal and on a fatal error
+    /// of the engine, rejected records are reported in the summary.
+    pub fn recover(
+        path: &Path,
+        engine: &mut TxEngine,
+    ) -> Result<(Journal, RecoverySummary), JournalError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut summary = RecoverySummary::default();
+
+        let mut reader = BufReader::new(&file);
+        let mut offset = 0;
+        loop {
+            let payload = match read_entry(&mut reader)? {
+                Entry::Complete(payload) => payload,
+                Entry::End => break,
+                // Only the last entry may be torn, it is truncated below
+                Entry::Torn { size } if offset + size >= file_len => break,
+                Entry::Torn { .. } | Entry::Corrupted => {
+                    return Err(JournalError::Corrupted { offset })
+                }
+            };
+            let record: TransactionRecord = serde_json::from_slice(&payload)
+                .map_err(|source| JournalError::InvalidRecord { offset, source })?;
+            match engine.process_tx(record) {
+                Ok(()) => summary.replayed += 1,
+                Err(e) if e.is_fatal() => return Err(JournalError::Replay { offset, source: e }),
+                // Only validated records are journaled, so a rejection means the commit
+                // of the record failed or the engine is configured differently than during
+                // the original run. The record is skipped either way.
+                Err(error) => summary.rejected.push(RejectedEntry { offset, error }),
+            }
+            offset += (HEADER_SIZE + payload.len()) as u64;
+        }
+
+        if offset < file_len {
+            file.set_len(offset)?;
+            summary.truncated_bytes = file_len - offset;
+        }
+
+        Ok((Journal { file }, summary))
+    }
+
+    /// The whole entry is written with a single call, to minimize the chance of a torn write
+    pub fn append(&mut self, record: &TransactionRecord) -> Result<(), JournalError> {
+        let payload = serde_json::to_vec(record).map_err(io::Error::from)?;
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Record is too large for the journal",
+            )
+            .into());
+        }
+        let mut entry = Vec::with_capacity(HEADER_SIZE + payload.len());
+        entry.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        entry.extend_from_slice(&payload);
+        self.file.write_all(&entry)?;
+        Ok(())
+    }
+
+    pub fn sync(&self) -> Result<(), JournalError> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Drops all entries, used once their effects are persisted in a snapshot
+    pub fn reset(&mut self) -> Result<(), JournalError> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+fn read_entry(reader: &mut impl Read) -> io::Result<Entry> {
+    let mut header = [0; HEADER_SIZE];
+    match read_full(reader, &mut header)? {
+        0 => return Ok(Entry::End),
+        HEADER_SIZE => {}
+        _ => {
+            return Ok(Entry::Torn {
+                size: HEADER_SIZE as u64,
+            })
+        }
+    }
+
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if len > MAX_PAYLOAD_SIZE {
+        return Ok(Entry::Corrupted);
+    }
+
+    // The payload is read through `take`, so a corrupted length does not allocate
+    // more than is available in the file
+    let mut payload = Vec::new();
+    reader.take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() != len || crc32fast::hash(&payload) != checksum {
+        return Ok(Entry::Torn {
+            size: (HEADER_SIZE + len) as u64,
+        });
+    }
+
+    Ok(Entry::Complete(payload))
+}
+
+/// Reads until the buffer is full or the end of the input, returns the number of bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+impl TxEngine {
+    /// Every validated record is appended to the journal before it is committed
+    pub fn attach_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub fn journal_mut(&mut self) -> Option<&mut Journal> {
+        self.journal.as_mut()
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    client::{Balance, Client},
+    TxEngine,
+};
+use crate::{
+    amount::Amount,
+    currency::Currency,
+    errors::{LedgerError, ProcessingError},
+    ClientId,
+};
+
+/// Number of committed deposits and withdrawals between the full checks of the ledger
+pub const LEDGER_CHECK_INTERVAL: u64 = 65_536;
+
+/// Account of the double-entry ledger. Every client has an available and a held account,
+/// the funds enter and leave the system through the external accounts.
+/// There is a separate set of the accounts for each currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Account {
+    /// Funds the client can use
+    Available(ClientId),
+    /// Funds of the client held by open disputes
+    Held(ClientId),
+    /// External account the funds are deposited from and withdrawn to
+    Settlement,
+    /// External account of the funds moved by chargebacks and withdrawal reversals
+    Chargebacks,
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Account::Available(client) => write!(f, "available account of client {client}"),
+            Account::Held(client) => write!(f, "held account of client {client}"),
+            Account::Settlement => write!(f, "settlement account"),
+            Account::Chargebacks => write!(f, "chargebacks account"),
+        }
+    }
+}
+
+/// Movement of an amount between two accounts in the same currency, `from` is debited
+/// and `to` credited by the same amount, so every posting is balanced by construction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Posting {
+    pub from: Account,
+    pub to: Account,
+    pub amount: Amount,
+    pub currency: Currency,
+}
+
+impl Posting {
+    pub fn new(from: Account, to: Account, amount: Amount, currency: Currency) -> Self {
+        Self {
+            from,
+            to,
+            amount,
+            currency,
+        }
+    }
+
+    /// Client whose account is a side of the posting, every operation moves the funds
+    /// of a single client
+    pub fn client(&self) -> Option<ClientId> {
+        [self.from, self.to]
+            .into_iter()
+            .find_map(|account| match account {
+                Account::Available(client) | Account::Held(client) => Some(client),
+                Account::Settlement | Account::Chargebacks => None,
+            })
+    }
+}
+
+/// Append-only log of the committed postings. The balance of every account is the sum
+/// of its postings, so the ledger check recalculates the balances from the log instead of
+/// trusting the ones kept by the clients and the ledger. The postings are never changed,
+/// so the sums of the already summed ones are kept and each check adds only the postings
+/// appended since the previous one.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PostingLog {
+    postings: Vec<Posting>,
+    #[serde(skip)]
+    sums: BTreeMap<(Account, Currency), i128>,
+    #[serde(skip)]
+    summed: usize,
+}
+
+impl PostingLog {
+    pub fn append(&mut self, posting: Posting) {
+        self.postings.push(posting);
+    }
+
+    pub fn into_postings(self) -> Vec<Posting> {
+        self.postings
+    }
+
+    /// Balances of the accounts as the sums of their postings, in ten-thousandths
+    fn balances(&mut self) -> &BTreeMap<(Account, Currency), i128> {
+        for posting in &self.postings[self.summed..] {
+            let units = i128::from(posting.amount.units());
+            *self
+                .sums
+                .entry((posting.from, posting.currency))
+                .or_default() -= units;
+            *self.sums.entry((posting.to, posting.currency)).or_default() += units;
+        }
+        self.summed = self.postings.len();
+        &self.sums
+    }
+}
+
+/// Balances of the external accounts. The client accounts are kept by the clients,
+/// both are updated only by applying postings, which are recorded in the posting log.
+/// A balance is the sum of the credits minus the sum of the debits, so the external accounts
+/// go negative as the funds enter the system, and the balances of all accounts in a currency
+/// always sum up to zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ledger {
+    settlement: BTreeMap<Currency, Amount>,
+    chargebacks: BTreeMap<Currency, Amount>,
+}
+
+impl Ledger {
+    /// Applies the sides of the posting on the external accounts
+    pub fn post(&mut self, posting: &Posting) -> Result<(), ProcessingError> {
+        let mut ledger = self.clone();
+        if let Some(balance) = ledger.external_balance(posting.from, posting.currency) {
+            *balance = balance
+                .checked_sub(posting.amount)
+                .ok_or(ProcessingError::AmountOverflow)?;
+        }
+        if let Some(balance) = ledger.external_balance(posting.to, posting.currency) {
+            *balance = balance
+                .checked_add(posting.amount)
+                .ok_or(ProcessingError::AmountOverflow)?;
+        }
+        *self = ledger;
+        Ok(())
+    }
+
+    /// Combines the external balances of ledgers of engines with disjoint clients
+    pub fn merge(&mut self, other: &Ledger) -> Result<(), LedgerError> {
+        let add = |balances: &mut BTreeMap<Currency, Amount>, other: &BTreeMap<_, _>| {
+            for (currency, amount) in other {
+                let balance = balances.entry(*currency).or_default();
+                *balance = balance
+                    .checked_add(*amount)
+                    .ok_or(LedgerError::Overflow(*currency))?;
+            }
+            Ok(())
+        };
+        add(&mut self.settlement, &other.settlement)?;
+        add(&mut self.chargebacks, &other.chargebacks)
+    }
+
+    /// Sum of the external accounts in the currency
+    fn external_sum(&self, currency: Currency) -> i128 {
+        [&self.settlement, &self.chargebacks]
+            .into_iter()
+            .filter_map(|balances| balances.get(&currency))
+            .map(|amount| i128::from(amount.units()))
+            .sum()
+    }
+
+    fn currencies(&self) -> impl Iterator<Item = Currency> + '_ {
+        self.settlement
+            .keys()
+            .chain(self.chargebacks.keys())
+            .copied()
+    }
+
+    fn external_balance(&mut self, account: Account, currency: Currency) -> Option<&mut Amount> {
+        let balances = match account {
+            Account::Settlement => &mut self.settlement,
+            Account::Chargebacks => &mut self.chargebacks,
+            Account::Available(_) | Account::Held(_) => return None,
+        };
+        Some(balances.entry(currency).or_default())
+    }
+}
+
+impl TxEngine {
+    /// Verifies the invariants of the ledger - the balance of every account is the sum of its
+    /// postings in the log, the balances of all accounts in each currency sum up to zero,
+    /// and no client holds a negative amount
+    pub fn check_ledger(&mut self) -> Result<(), LedgerError> {
+        let units = |amount: Amount| i128::from(amount.units());
+        let mut balances: BTreeMap<(Account, Currency), i128> = BTreeMap::new();
+        for (account, external) in [
+            (Account::Settlement, &self.ledger.settlement),
+            (Account::Chargebacks, &self.ledger.chargebacks),
+        ] {
+            for (currency, amount) in external {
+                balances.insert((account, *currency), units(*amount));
+            }
+        }
+        for client in self.clients_store.iter() {
+            for (currency, balance) in client.balances() {
+                if balance.held() < Amount::ZERO {
+                    return Err(LedgerError::NegativeHeld(client.id()));
+                }
+                let id = client.id();
+                balances.insert(
+                    (Account::Available(id), currency),
+                    units(balance.available()),
+                );
+                balances.insert((Account::Held(id), currency), units(balance.held()));
+            }
+        }
+
+        let logged = self.postings.balances();
+        let balance_of = |balances: &BTreeMap<_, i128>, key| balances.get(key).copied();
+        let mismatch = balances.keys().chain(logged.keys()).find(|key| {
+            balance_of(&balances, key).unwrap_or_default()
+                != balance_of(logged, key).unwrap_or_default()
+        });
+        if let Some((account, currency)) = mismatch {
+            return Err(LedgerError::LogMismatch(format!("{account} in {currency}")));
+        }
+
+        let mut sums: BTreeMap<Currency, i128> = BTreeMap::new();
+        for ((_, currency), units) in balances {
+            *sums.entry(currency).or_default() += units;
+        }
+        for (currency, sum) in sums {
+            if sum != 0 {
+                return Err(imbalance(sum, currency));
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies that a commit keeps the ledger balanced - in each currency, the change
+    /// of the balances of the client is offset by the change of the external accounts,
+    /// and the client holds no negative amount. Only the client and the external accounts
+    /// are compared, so it's cheap enough for every commit.
+    pub(super) fn check_commit(&self, client: &Client, ledger: &Ledger) -> Result<(), LedgerError> {
+        let previous = self.clients_store.get_client(client.id());
+        let currencies: BTreeSet<_> = client
+            .balances()
+            .map(|(currency, _)| currency)
+            .chain(ledger.currencies())
+            .chain(self.ledger.currencies())
+            .collect();
+        let units = |balance: Balance| {
+            i128::from(balance.available().units()) + i128::from(balance.held().units())
+        };
+        for currency in currencies {
+            let balance = client.balance(currency);
+            if balance.held() < Amount::ZERO {
+                return Err(LedgerError::NegativeHeld(client.id()));
+            }
+            let previous = previous.map(|client| client.balance(currency));
+            let change = units(balance) - previous.map_or(0, units) + ledger.external_sum(currency)
+                - self.ledger.external_sum(currency);
+            if change != 0 {
+                return Err(imbalance(change, currency));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn imbalance(units: i128, currency: Currency) -> LedgerError {
+    let amount = i64::try_from(units)
+        .map(|units| Amount::from_units(units).to_string())
+        .unwrap_or_else(|_| format!("{units} ten-thousandths"));
+    LedgerError::Imbalance(format!("{amount} {currency}"))
+}
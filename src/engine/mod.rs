@@ -1,74 +1,382 @@
-use client::{Client, ClientStore};
-use transaction::{Transaction, TransactionStore};
+use std::collections::BTreeMap;
+
+use client::ClientStore;
+pub use client::{Client, ClientBalance, ClientOrder};
+pub use journal::Journal;
+use ledger::{Ledger, Posting, PostingLog};
+pub use options::{
+    Currencies, DisputeWindow, EngineOptions, LockedAccountPolicy, OutOfOrderPolicy,
+    WithdrawalDisputePolicy,
+};
+use rules::ClientActivity;
+pub use rules::{RiskRule, RiskRules};
+pub use sharded::{ShardedEngine, MAX_SHARDS};
+use transaction::TransactionStore;
+pub use transaction::{
+    DiskStorage, Transaction, TransactionKind, TransactionState, TransactionStorage,
+};
 
 use crate::{
-    errors::ProcessingError,
+    amount::Amount,
+    currency::Currency,
+    errors::{LedgerError, ProcessingError, TransactionError},
+    timestamp::Timestamp,
     transaction_record::{TransactionRecord, TransactionRecordType},
+    ClientId, TransactionId,
 };
 
 mod client;
+mod expiry;
+mod journal;
+mod ledger;
+mod options;
+mod rules;
+mod sharded;
+mod snapshot;
 #[cfg(test)]
 mod tests;
 mod transaction;
 
+enum TxChange {
+    Insert(Transaction),
+    Update(Transaction),
+    /// Only the client is changed, e.g. by an admin record
+    None,
+}
+
+/// Effects of a validated transaction, which are not applied to the engine yet
+pub struct PreparedTx {
+    client: Client,
+    change: TxChange,
+    /// Ledger with the posting of the transaction applied
+    ledger: Ledger,
+    /// Posting of the transaction, appended to the log once it's committed
+    posting: Option<Posting>,
+    /// Latest timestamp seen, including the one of the transaction
+    clock: Option<Timestamp>,
+    /// Activity of the client including the transaction, if it's tracked for the risk rules
+    activity: Option<ClientActivity>,
+}
+
 #[derive(Default)]
 pub struct TxEngine {
     clients_store: ClientStore,
     committed_txs: TransactionStore,
+    options: EngineOptions,
+    journal: Option<Journal>,
+    ledger: Ledger,
+    /// Every committed posting, the balances are checked against
+    postings: PostingLog,
+    /// Number of committed deposits and withdrawals, the sequence number of the next one
+    committed: u64,
+    /// Latest timestamp of the accepted records
+    clock: Option<Timestamp>,
+    /// Activity of the clients the risk rules are evaluated against,
+    /// tracked only when any rule is set
+    activity: BTreeMap<ClientId, ClientActivity>,
+    /// Fatal error of an earlier record. The state may differ from the journal or be partially
+    /// applied, so every later record is rejected with it.
+    failure: Option<ProcessingError>,
 }
 
 impl TxEngine {
+    pub fn new(options: EngineOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Engine keeping the committed transactions in the given storage,
+    /// instead of the default in-memory one
+    pub fn with_storage(options: EngineOptions, storage: Box<dyn TransactionStorage>) -> Self {
+        Self {
+            committed_txs: TransactionStore::new(storage),
+            ..Self::new(options)
+        }
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    /// The record is journaled once it's validated, before it's committed, so an applied record
+    /// is always in the journal. A record whose commit fails stays in the journal - the engine
+    /// stops, and the replay applies the record again or skips it if it's rejected.
     pub fn process_tx(&mut self, tx: TransactionRecord) -> Result<(), ProcessingError> {
-        let client = self.clients_store.get_client_mut(tx.client);
+        if let Some(failure) = &self.failure {
+            return Err(failure.clone());
+        }
+        let prepared = self.prepare_tx(&tx)?;
+        let result = match &mut self.journal {
+            Some(journal) => journal
+                .append(&tx)
+                .map_err(|e| ProcessingError::Journal(e.to_string())),
+            None => Ok(()),
+        }
+        .and_then(|()| self.commit_tx(prepared));
+        if let Some(e) = result.as_ref().err().filter(|e| e.is_fatal()) {
+            self.failure = Some(e.clone());
+        }
+        result
+    }
+
+    /// Validates the transaction against the current state and calculates its effects,
+    /// without applying them. The client and the referred transaction are cloned,
+    /// so nothing has to be reverted when any of the operations fails.
+    pub fn prepare_tx(&self, tx: &TransactionRecord) -> Result<PreparedTx, ProcessingError> {
+        if self.options.out_of_order_policy != OutOfOrderPolicy::Accept {
+            if let (Some(timestamp), Some(latest)) = (tx.timestamp, self.clock) {
+                if timestamp < latest {
+                    return Err(ProcessingError::OutOfOrder { latest });
+                }
+            }
+        }
+
+        let currency = self.record_currency(tx);
+        if !self.options.currencies.accepted.contains(&currency) {
+            return Err(ProcessingError::UnknownCurrency(currency));
+        }
 
-        let tx_to_store = match tx.tx_type {
+        // Rules are evaluated once the record is known to be valid, before the client
+        // is touched. Ids are checked first, the same way the sharded engine does.
+        let rules = &self.options.risk_rules;
+        let check_rules = || -> Result<Option<ClientActivity>, ProcessingError> {
+            if rules.is_empty() {
+                return Ok(None);
+            }
+            let current = self.activity.get(&tx.client).cloned().unwrap_or_default();
+            rules.check(tx, currency, &current).map(Some)
+        };
+
+        let mut client = self
+            .clients_store
+            .get_client(tx.client)
+            .cloned()
+            .unwrap_or_else(|| Client::new(tx.client));
+
+        let mut posting = None;
+        let activity;
+        let change = match tx.tx_type {
+            // The id is checked upfront, so the client is not touched when it is reused
             TransactionRecordType::Deposit { amount } => {
-                client.deposit(amount)?;
-                Transaction::new(tx.tx, amount, tx.client)
+                self.committed_txs.check_unused(&tx.tx)?;
+                activity = check_rules()?;
+                posting = Some(client.deposit(amount.get(), currency)?);
+                TxChange::Insert(Transaction::new(
+                    tx.tx,
+                    TransactionKind::Deposit,
+                    amount.get(),
+                    currency,
+                    tx.client,
+                    self.committed,
+                    tx.timestamp,
+                ))
             }
             TransactionRecordType::Withdrawal { amount } => {
-                client.withdraw(amount)?;
-                Transaction::new(tx.tx, amount, tx.client)
+                self.committed_txs.check_unused(&tx.tx)?;
+                activity = check_rules()?;
+                let credit_limit = self.credit_limit(&client, currency);
+                posting = Some(client.withdraw(amount.get(), currency, credit_limit)?);
+                TxChange::Insert(Transaction::new(
+                    tx.tx,
+                    TransactionKind::Withdrawal,
+                    amount.get(),
+                    currency,
+                    tx.client,
+                    self.committed,
+                    tx.timestamp,
+                ))
             }
             TransactionRecordType::Dispute
             | TransactionRecordType::Resolve
             | TransactionRecordType::Chargeback => {
-                // In the below cases a clone of the transaction is created.
-                // This is done to avoid mutating the original transaction
-                // what would need to be reverted in case of any further errors.
-                // When operation in client fails, error is returned before the transaction is inserted into the store.
                 let referred_tx = self.committed_txs.get(&tx.tx)?;
 
                 if referred_tx.client_id() != tx.client {
                     return Err(ProcessingError::ClientIdNotMatched);
                 }
+                // A record without a currency refers to the transaction in any currency
+                let currency = referred_tx.currency();
+                if tx.currency.is_some_and(|given| given != currency) {
+                    return Err(TransactionError::CurrencyMismatch.into());
+                }
+                activity = check_rules()?;
 
-                match tx.tx_type {
+                // Only new disputes are rejected by the policy, a withdrawal already under
+                // dispute is settled with the correct (reversal) semantics.
+                let policy = self.options.withdrawal_dispute_policy;
+                let locked_policy = self.options.locked_account_policy;
+                let reverses_withdrawal = referred_tx.kind() == TransactionKind::Withdrawal
+                    && policy != WithdrawalDisputePolicy::Legacy;
+                let amount = referred_tx.get_amount();
+
+                let modified_tx = match tx.tx_type {
                     TransactionRecordType::Dispute => {
+                        if let Some(window) = self.options.dispute_window {
+                            let now = tx.timestamp.or(self.clock);
+                            if window.is_expired(&referred_tx, self.committed, now) {
+                                return Err(TransactionError::DisputeWindowExpired.into());
+                            }
+                        }
+                        if referred_tx.kind() == TransactionKind::Withdrawal
+                            && policy == WithdrawalDisputePolicy::Reject
+                        {
+                            return Err(TransactionError::WithdrawalNotDisputable.into());
+                        }
                         let modified_tx = referred_tx.clone().disputed()?;
-                        client.dispute(referred_tx.get_amount())?;
+                        if reverses_withdrawal {
+                            posting = Some(client.dispute_withdrawal(amount, currency)?);
+                        } else {
+                            posting = Some(client.dispute(amount, currency)?);
+                        }
+                        if activity.as_ref().is_some_and(|a| rules.locks_on_dispute(a)) {
+                            client.lock();
+                        }
                         modified_tx
                     }
                     TransactionRecordType::Resolve => {
                         let modified_tx = referred_tx.clone().resolved()?;
-                        client.resolve(referred_tx.get_amount())?;
+                        if reverses_withdrawal {
+                            posting =
+                                Some(client.resolve_withdrawal(amount, currency, locked_policy)?);
+                        } else {
+                            posting = Some(client.resolve(amount, currency, locked_policy)?);
+                        }
                         modified_tx
                     }
                     TransactionRecordType::Chargeback => {
                         let modified_tx = referred_tx.clone().charged_back()?;
-                        client.charge_back(referred_tx.get_amount())?;
+                        if reverses_withdrawal {
+                            posting = Some(client.charge_back_withdrawal(
+                                amount,
+                                currency,
+                                locked_policy,
+                            )?);
+                        } else {
+                            posting = Some(client.charge_back(amount, currency, locked_policy)?);
+                        }
                         modified_tx
                     }
                     _ => unreachable!(),
-                }
+                };
+
+                TxChange::Update(modified_tx)
+            }
+            TransactionRecordType::Unlock { .. } => {
+                activity = check_rules()?;
+                client.unlock()?;
+                TxChange::None
+            }
+            TransactionRecordType::Limit { limit, .. } => {
+                activity = check_rules()?;
+                client.set_credit_limit(currency, limit.map_or(Amount::ZERO, |limit| limit.get()));
+                TxChange::None
             }
         };
 
-        self.committed_txs.insert(tx_to_store);
+        let mut ledger = self.ledger.clone();
+        if let Some(posting) = &posting {
+            ledger.post(posting)?;
+        }
+
+        Ok(PreparedTx {
+            client,
+            change,
+            ledger,
+            posting,
+            clock: self.clock.max(tx.timestamp),
+            activity,
+        })
+    }
+
+    /// Applies the effects of a prepared transaction to the engine state
+    pub fn commit_tx(&mut self, prepared: PreparedTx) -> Result<(), ProcessingError> {
+        let ledger_error = |e: LedgerError| ProcessingError::Ledger(e.to_string());
+        self.check_commit(&prepared.client, &prepared.ledger)
+            .map_err(ledger_error)?;
+        let inserted = matches!(prepared.change, TxChange::Insert(_));
+        match prepared.change {
+            TxChange::Insert(tx) => self.committed_txs.insert(tx)?,
+            TxChange::Update(tx) => self.committed_txs.update(tx)?,
+            TxChange::None => {}
+        }
+        self.ledger = prepared.ledger;
+        if let Some(posting) = prepared.posting {
+            self.postings.append(posting);
+        }
+        if let Some(activity) = prepared.activity {
+            self.activity.insert(prepared.client.id(), activity);
+        }
+        self.clients_store.put(prepared.client);
+        self.clock = prepared.clock;
+        if inserted {
+            self.committed += 1;
+            // The commits are checked one by one, the whole ledger is checked
+            // once in a while, so long-lived engines are verified as well
+            if self.committed.is_multiple_of(ledger::LEDGER_CHECK_INTERVAL) {
+                self.check_ledger().map_err(ledger_error)?;
+            }
+            if self.options.evict_expired
+                && self.committed.is_multiple_of(expiry::EVICTION_INTERVAL)
+            {
+                self.evict_expired()?;
+            }
+        }
         Ok(())
     }
 
+    pub fn get_client(&self, id: ClientId) -> Option<&Client> {
+        self.clients_store.get_client(id)
+    }
+
+    /// Deposit or withdrawal with the given id, in its current dispute state
+    pub fn get_transaction(
+        &self,
+        id: TransactionId,
+    ) -> Result<Option<Transaction>, ProcessingError> {
+        match self.committed_txs.get(&id) {
+            Ok(tx) => Ok(Some(tx)),
+            Err(ProcessingError::InvalidTransaction(TransactionError::ReferredTxNotFound)) => {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deposits and withdrawals of the client in the order they were committed,
+    /// each in its current dispute state
+    pub fn client_transactions(
+        &self,
+        client: ClientId,
+    ) -> impl Iterator<Item = Result<Transaction, ProcessingError>> + '_ {
+        self.committed_txs.client_transactions(client)
+    }
+
+    /// Clients ordered by their id
     pub fn get_clients(&self) -> impl Iterator<Item = &Client> {
-        self.clients_store.get_clients()
+        self.clients_store.iter()
+    }
+
+    /// Balances of the clients, one for each client and currency it has used
+    pub fn get_balances(&self, order: ClientOrder) -> impl Iterator<Item = ClientBalance> {
+        self.clients_store.get_balances(order)
+    }
+
+    /// Credit limit of the client in the currency, the one set by a limit record
+    /// or the configured one
+    pub fn credit_limit(&self, client: &Client, currency: Currency) -> Amount {
+        client
+            .credit_limit(currency)
+            .or_else(|| {
+                let key = (client.id(), currency);
+                self.options.credit_limits.get(&key).copied()
+            })
+            .unwrap_or(Amount::ZERO)
+    }
+
+    /// Currency of the record, the default one if the record doesn't give it
+    pub fn record_currency(&self, tx: &TransactionRecord) -> Currency {
+        tx.currency.unwrap_or(self.options.currencies.default)
     }
 }
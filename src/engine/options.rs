@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use super::RiskRules;
+use crate::{amount::Amount, currency::Currency, ClientId};
+
+/// Defines how disputes referring to a withdrawal are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WithdrawalDisputePolicy {
+    /// Disputes referring to a withdrawal are rejected
+    Reject,
+    /// The withdrawn funds are held during the dispute and credited back on chargeback
+    Reverse,
+    /// A withdrawal is disputed in the same way as a deposit
+    #[default]
+    Legacy,
+}
+
+/// Defines which operations are still allowed on a locked account
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LockedAccountPolicy {
+    /// Every operation is rejected until the account is unlocked
+    #[default]
+    RejectAll,
+    /// Disputes opened before the lock can still be resolved or charged back,
+    /// deposits, withdrawals and new disputes are rejected
+    SettleDisputes,
+}
+
+/// How long a deposit or withdrawal can be disputed after it was committed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeWindow {
+    /// At most the given number of deposits and withdrawals may be committed after it
+    Transactions(u64),
+    /// At most the given number of seconds may pass, by the timestamps of the records.
+    /// Transactions without a timestamp can always be disputed.
+    Seconds(u64),
+}
+
+/// Defines how records with a timestamp older than the latest accepted one are handled.
+/// Records without a timestamp are never out of order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutOfOrderPolicy {
+    /// Records are processed in the input order, whatever their timestamps
+    #[default]
+    Accept,
+    /// Records older than the latest accepted one are rejected
+    Reject,
+    /// Records of each input are reordered by their timestamps within a bounded buffer,
+    /// records still out of order are rejected
+    Resequence,
+}
+
+/// Currencies the engine keeps balances in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Currencies {
+    /// Records in other currencies are rejected
+    pub accepted: Vec<Currency>,
+    /// Currency of the records that don't give one
+    pub default: Currency,
+}
+
+impl Default for Currencies {
+    fn default() -> Self {
+        Self {
+            accepted: vec![Currency::EUR, Currency::USD, Currency::GBP],
+            default: Currency::EUR,
+        }
+    }
+}
+
+/// Engine-level settings that change how transactions are processed
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    pub withdrawal_dispute_policy: WithdrawalDisputePolicy,
+    pub locked_account_policy: LockedAccountPolicy,
+    /// Disputes are not limited in time without a window
+    pub dispute_window: Option<DisputeWindow>,
+    /// Expired transactions that are not under dispute are dropped from the store
+    pub evict_expired: bool,
+    pub out_of_order_policy: OutOfOrderPolicy,
+    /// Number of records held back to reorder an input under the `Resequence` policy
+    pub resequence_buffer: usize,
+    pub currencies: Currencies,
+    /// Credit limits of the clients by the currency, loaded from a file.
+    /// Limits set by limit records take precedence, clients without a limit have none.
+    pub credit_limits: BTreeMap<(ClientId, Currency), Amount>,
+    pub risk_rules: RiskRules,
+}
+
+impl EngineOptions {
+    /// Size of the buffer the inputs are reordered with, if they are reordered
+    pub fn resequence_buffer(&self) -> Option<usize> {
+        (self.out_of_order_policy == OutOfOrderPolicy::Resequence).then_some(self.resequence_buffer)
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::{self, Display},
+    num::NonZeroU64,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    amount::Amount,
+    currency::Currency,
+    errors::ProcessingError,
+    transaction_record::{TransactionRecord, TransactionRecordType},
+    ClientId,
+};
+
+/// Risk rules evaluated before a record is applied to the client, loaded from a JSON file.
+/// All rules are optional, without any rule every record is passed to the client.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RiskRules {
+    /// Largest amount of a single withdrawal, by the currency. Withdrawals in the other
+    /// currencies are not limited.
+    #[serde(default)]
+    pub max_withdrawal: BTreeMap<Currency, Amount>,
+    pub withdrawal_velocity: Option<WithdrawalVelocity>,
+    /// Number of accepted disputes of a client that locks it. Further disputes are rejected
+    /// until the client is unlocked, which starts the count again.
+    pub max_disputes: Option<u32>,
+    /// Clients whose records are all rejected
+    #[serde(default)]
+    pub deny_list: BTreeSet<ClientId>,
+}
+
+/// At most `max_withdrawals` withdrawals within any `records` consecutive accepted
+/// records of a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WithdrawalVelocity {
+    pub max_withdrawals: usize,
+    pub records: NonZeroU64,
+}
+
+/// Rule a record is rejected by, named in the rejection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskRule {
+    MaxWithdrawal,
+    WithdrawalVelocity,
+    MaxDisputes,
+    DenyList,
+}
+
+impl RiskRule {
+    /// Machine-readable code of the records rejected by the rule, used in the rejection report
+    pub fn code(self) -> &'static str {
+        match self {
+            RiskRule::MaxWithdrawal => "rule_violation:max_withdrawal",
+            RiskRule::WithdrawalVelocity => "rule_violation:withdrawal_velocity",
+            RiskRule::MaxDisputes => "rule_violation:max_disputes",
+            RiskRule::DenyList => "rule_violation:deny_list",
+        }
+    }
+}
+
+impl Display for RiskRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RiskRule::MaxWithdrawal => "max_withdrawal",
+            RiskRule::WithdrawalVelocity => "withdrawal_velocity",
+            RiskRule::MaxDisputes => "max_disputes",
+            RiskRule::DenyList => "deny_list",
+        })
+    }
+}
+
+/// Activity of a client the rules are evaluated against. Only the accepted records
+/// are counted, so the activity is the same whether the clients are sharded or not.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientActivity {
+    /// Number of the accepted records of the client
+    records: u64,
+    /// Numbers of the accepted withdrawals among the records, only the ones
+    /// still within the velocity window are kept
+    withdrawals: VecDeque<u64>,
+    /// Accepted disputes since the client was last unlocked
+    disputes: u32,
+}
+
+fn violation(rule: RiskRule, detail: String) -> ProcessingError {
+    ProcessingError::RuleViolation { rule, detail }
+}
+
+impl RiskRules {
+    /// Whether any rule is set, the activity of the clients is tracked only then
+    pub fn is_empty(&self) -> bool {
+        *self == RiskRules::default()
+    }
+
+    /// Checks the record against the rules, nothing of the client is touched yet.
+    /// Returns the activity of the client updated by the record, to be committed
+    /// together with the record.
+    pub fn check(
+        &self,
+        tx: &TransactionRecord,
+        currency: Currency,
+        activity: &ClientActivity,
+    ) -> Result<ClientActivity, ProcessingError> {
+        if self.deny_list.contains(&tx.client) {
+            return Err(violation(
+                RiskRule::DenyList,
+                format!("client {} is denied", tx.client),
+            ));
+        }
+
+        let mut activity = activity.clone();
+        let record = activity.records;
+        activity.records += 1;
+        match &tx.tx_type {
+            TransactionRecordType::Withdrawal { amount } => {
+                let amount = amount.get();
+                if let Some(max) = self.max_withdrawal.get(&currency) {
+                    if amount > *max {
+                        return Err(violation(
+                            RiskRule::MaxWithdrawal,
+                            format!("{amount} {currency} is over the maximum of {max} {currency}"),
+                        ));
+                    }
+                }
+                if let Some(velocity) = self.withdrawal_velocity {
+                    let window_start = (record + 1).saturating_sub(velocity.records.get());
+                    activity.withdrawals.retain(|&n| n >= window_start);
+                    if activity.withdrawals.len() >= velocity.max_withdrawals {
+                        return Err(violation(
+                            RiskRule::WithdrawalVelocity,
+                            format!(
+                                "{} withdrawals within the last {} records",
+                                activity.withdrawals.len(),
+                                velocity.records
+                            ),
+                        ));
+                    }
+                    activity.withdrawals.push_back(record);
+                }
+            }
+            TransactionRecordType::Dispute => {
+                if let Some(max) = self.max_disputes {
+                    if activity.disputes >= max {
+                        return Err(violation(
+                            RiskRule::MaxDisputes,
+                            format!("{} disputes since the last unlock", activity.disputes),
+                        ));
+                    }
+                }
+                activity.disputes += 1;
+            }
+            TransactionRecordType::Unlock { .. } => activity.disputes = 0,
+            _ => {}
+        }
+        Ok(activity)
+    }
+
+    /// Whether the client is locked by the dispute that brought it to the activity
+    pub fn locks_on_dispute(&self, activity: &ClientActivity) -> bool {
+        self.max_disputes
+            .is_some_and(|max| activity.disputes >= max)
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use super::TxEngine;
+use crate::{
+    errors::{ProcessingError, TransactionError},
+    transaction_record::{TransactionRecord, TransactionRecordType},
+    ClientId, TransactionId,
+};
+
+/// Maximal number of shards, limited by the size of the shard set bitmask
+pub const MAX_SHARDS: usize = 64;
+
+/// Records are sent to the workers in batches, to reduce the synchronization overhead
+const BATCH_SIZE: usize = 1024;
+/// Number of messages queued for each worker, bounds the memory used when a worker lags behind
+const WORKER_QUEUE_SIZE: usize = 16;
+
+enum Message<C> {
+    Process(Vec<(TransactionRecord, C)>),
+    IsCommitted(TransactionId, Sender<bool>),
+}
+
+struct Worker<C> {
+    sender: SyncSender<Message<C>>,
+    batch: Vec<(TransactionRecord, C)>,
+    handle: JoinHandle<TxEngine>,
+}
+
+/// Engine processing records in parallel. Every operation touches a single client only,
+/// so each record is routed by its client id to one of the worker engines, which process
+/// their records in the input order.
+///
+/// The only state shared between clients are transaction ids. The router remembers which
+/// shards received a deposit or withdrawal with a given id, and when a record refers to an id
+/// used in another shard, it asks that shard whether the transaction was committed. The query
+/// is queued behind all previously routed records, so the answer is the same as the sequential
+/// engine would give. Such conflicts are rare, so the blocking query is cheap in practice.
+///
+/// Errors are returned together with a context given for each record.
+pub struct ShardedEngine<C> {
+    workers: Vec<Worker<C>>,
+    /// Bitmask of shards that received a deposit or withdrawal with the transaction id
+    tx_shards: HashMap<TransactionId, u64>,
+    /// Number of transactions committed before the split, all shards count from it
+    committed: u64,
+    errors_sender: Sender<(C, ProcessingError)>,
+    errors: Receiver<(C, ProcessingError)>,
+}
+
+impl<C: Send + 'static> ShardedEngine<C> {
+    /// Splits the state of the engine between the given number of shards. The shards keep
+    /// their transactions in memory, whatever storage the engine uses.
+    pub fn new(engine: TxEngine, shards: usize) -> Result<Self, ProcessingError> {
+        assert!((1..=MAX_SHARDS).contains(&shards));
+
+        let mut tx_shards = HashMap::new();
+        for tx in engine.committed_txs.iter() {
+            let tx = tx?;
+            tx_shards.insert(tx.id(), 1 << shard_of(tx.client_id(), shards));
+        }
+
+        let (errors_sender, errors) = mpsc::channel();
+        let committed = engine.committed;
+        let workers = engine
+            .into_shards(shards, |client| shard_of(client, shards))?
+            .into_iter()
+            .map(|engine| {
+                let (sender, receiver) = mpsc::sync_channel(WORKER_QUEUE_SIZE);
+                let errors_sender = errors_sender.clone();
+                let handle = thread::spawn(move || run_worker(engine, receiver, errors_sender));
+                Worker {
+                    sender,
+                    batch: Vec::with_capacity(BATCH_SIZE),
+                    handle,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            workers,
+            tx_shards,
+            committed,
+            errors_sender,
+            errors,
+        })
+    }
+
+    pub fn process_tx(&mut self, tx: TransactionRecord, context: C) {
+        let shard = shard_of(tx.client, self.workers.len());
+        let other_shards = self.tx_shards.get(&tx.tx).copied().unwrap_or(0) & !(1 << shard);
+
+        // Admin records do not refer to transactions, the id is not checked
+        let refers_to_tx = !tx.tx_type.is_admin();
+
+        if refers_to_tx && other_shards != 0 && self.is_committed_in(other_shards, tx.tx) {
+            // At most one transaction with the id is committed, and it belongs to another client
+            let error = match tx.tx_type {
+                TransactionRecordType::Deposit { .. }
+                | TransactionRecordType::Withdrawal { .. } => {
+                    TransactionError::DuplicateTransactionId.into()
+                }
+                _ => ProcessingError::ClientIdNotMatched,
+            };
+            self.errors_sender
+                .send((context, error))
+                .expect("Errors receiver is owned by the engine");
+            return;
+        }
+
+        if let TransactionRecordType::Deposit { .. } | TransactionRecordType::Withdrawal { .. } =
+            tx.tx_type
+        {
+            *self.tx_shards.entry(tx.tx).or_default() |= 1 << shard;
+        }
+        let batch = &mut self.workers[shard].batch;
+        batch.push((tx, context));
+        if batch.len() >= BATCH_SIZE {
+            self.flush(shard);
+        }
+    }
+
+    /// Returns the errors reported by the workers so far
+    pub fn take_errors(&mut self) -> Vec<(C, ProcessingError)> {
+        self.errors.try_iter().collect()
+    }
+
+    /// Waits for all workers to finish, merges their state and returns the remaining errors.
+    /// Fails if the merged state is out of range.
+    pub fn finish(mut self) -> Result<(TxEngine, Vec<(C, ProcessingError)>), ProcessingError> {
+        (0..self.workers.len()).for_each(|shard| self.flush(shard));
+        let engines = self
+            .workers
+            .into_iter()
+            .map(|worker| {
+                drop(worker.sender);
+                worker.handle.join().expect("Worker thread panicked")
+            })
+            .collect();
+        drop(self.errors_sender);
+
+        Ok((
+            TxEngine::merge(engines, self.committed)?,
+            self.errors.into_iter().collect(),
+        ))
+    }
+
+    /// The pending batch is sent before the query, so the query sees the effects
+    /// of all records routed to the shard so far
+    fn is_committed_in(&mut self, shards: u64, id: TransactionId) -> bool {
+        (0..self.workers.len())
+            .filter(|shard| shards & (1 << shard) != 0)
+            .any(|shard| {
+                self.flush(shard);
+                let (sender, receiver) = mpsc::channel();
+                self.send(shard, Message::IsCommitted(id, sender));
+                receiver.recv().expect("Worker thread stopped")
+            })
+    }
+
+    fn flush(&mut self, shard: usize) {
+        let batch = std::mem::replace(
+            &mut self.workers[shard].batch,
+            Vec::with_capacity(BATCH_SIZE),
+        );
+        if !batch.is_empty() {
+            self.send(shard, Message::Process(batch));
+        }
+    }
+
+    fn send(&self, shard: usize, message: Message<C>) {
+        self.workers[shard]
+            .sender
+            .send(message)
+            .expect("Worker thread stopped");
+    }
+}
+
+fn shard_of(client: ClientId, shards: usize) -> usize {
+    client as usize % shards
+}
+
+fn run_worker<C>(
+    mut engine: TxEngine,
+    receiver: Receiver<Message<C>>,
+    errors: Sender<(C, ProcessingError)>,
+) -> TxEngine {
+    for message in receiver {
+        match message {
+            Message::Process(batch) => {
+                for (tx, context) in batch {
+                    if let Err(e) = engine.process_tx(tx) {
+                        // The receiver is dropped only after all workers finished
+                        let _ = errors.send((context, e));
+                    }
+                }
+            }
+            Message::IsCommitted(id, reply) => {
+                let _ = reply.send(engine.committed_txs.check_unused(&id).is_err());
+            }
+        }
+    }
+    engine
+}
+
+impl TxEngine {
+    /// Splits the state between engines by the client, the journal is not carried over.
+    /// The posting log is split by the client as well and every engine rebuilds its external
+    /// accounts from its postings, so the ledger of each engine matches its log and is balanced
+    /// on its own, and the merged one is the original. The shards count their committed
+    /// transactions and advance their clocks separately, so a dispute window differs
+    /// from a sequential run.
+    fn into_shards(
+        self,
+        shards: usize,
+        shard_of: impl Fn(ClientId) -> usize,
+    ) -> Result<Vec<TxEngine>, ProcessingError> {
+        let mut engines: Vec<_> = (0..shards)
+            .map(|_| TxEngine::new(self.options.clone()))
+            .collect();
+        for engine in &mut engines {
+            engine.committed = self.committed;
+            engine.clock = self.clock;
+        }
+        for client in self.clients_store.into_iter() {
+            engines[shard_of(client.id())].clients_store.put(client);
+        }
+        for posting in self.postings.into_postings() {
+            let engine = &mut engines[posting.client().map_or(0, &shard_of)];
+            engine.ledger.post(&posting)?;
+            engine.postings.append(posting);
+        }
+        for (client, activity) in self.activity {
+            engines[shard_of(client)].activity.insert(client, activity);
+        }
+        for tx in self.committed_txs.iter() {
+            let tx = tx?;
+            engines[shard_of(tx.client_id())]
+                .committed_txs
+                .insert(tx)
+                .expect("Transaction ids are unique");
+        }
+        Ok(engines)
+    }
+
+    /// Merges engines with disjoint clients and transactions, split from an engine
+    /// that committed `base` transactions. The sequence numbers of the transactions committed
+    /// by the shards overlap, so they are renumbered after `base`. The order between
+    /// the shards is not known, their transactions are interleaved by the shard sequence
+    /// and their posting logs are appended one after another, so only the postings
+    /// of each client keep their order.
+    fn merge(engines: Vec<TxEngine>, base: u64) -> Result<TxEngine, ProcessingError> {
+        let mut merged = TxEngine::new(
+            engines
+                .first()
+                .map(|engine| engine.options.clone())
+                .unwrap_or_default(),
+        );
+        let mut split_txs = Vec::new();
+        merged.committed = base;
+        for (shard, engine) in engines.into_iter().enumerate() {
+            merged
+                .ledger
+                .merge(&engine.ledger)
+                .map_err(|e| ProcessingError::Ledger(e.to_string()))?;
+            for posting in engine.postings.into_postings() {
+                merged.postings.append(posting);
+            }
+            merged.committed += engine.committed - base;
+            merged.clock = merged.clock.max(engine.clock);
+            for client in engine.clients_store.into_iter() {
+                merged.clients_store.put(client);
+            }
+            merged.activity.extend(engine.activity);
+            for tx in engine.committed_txs.iter() {
+                let tx = tx.expect("Shards keep the transactions in memory");
+                if tx.sequence() < base {
+                    merged
+                        .committed_txs
+                        .insert(tx)
+                        .expect("Transaction ids are unique between shards");
+                } else {
+                    split_txs.push((tx.sequence(), shard, tx));
+                }
+            }
+        }
+
+        // The transactions of a client are all in one shard, so they keep their order
+        split_txs.sort_unstable_by_key(|&(sequence, shard, _)| (sequence, shard));
+        for (sequence, (_, _, tx)) in (base..).zip(split_txs) {
+            merged
+                .committed_txs
+                .insert(tx.resequenced(sequence))
+                .expect("Transaction ids are unique between shards");
+        }
+        Ok(merged)
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{Read, Write},
+};
+
+use serde::{
+    de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::SerializeSeq,
+    Deserializer, Serialize, Serializer,
+};
+
+use super::{
+    client::Client,
+    ledger::{Ledger, PostingLog},
+    rules::ClientActivity,
+    transaction::{Transaction, TransactionStore},
+    TxEngine,
+};
+use crate::{
+    errors::{ProcessingError, SnapshotError, TransactionError},
+    timestamp::Timestamp,
+    ClientId,
+};
+
+/// Version of the snapshot format, has to be bumped on every change of the persisted state
+pub const SNAPSHOT_VERSION: u32 = 7;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    clients: Vec<&'a Client>,
+    transactions: Transactions<'a>,
+    ledger: &'a Ledger,
+    postings: &'a PostingLog,
+    committed: u64,
+    clock: Option<Timestamp>,
+    activity: &'a BTreeMap<ClientId, ClientActivity>,
+}
+
+/// Streams the transactions from the store, which may keep them on disk
+struct Transactions<'a>(&'a TransactionStore);
+
+impl Serialize for Transactions<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for tx in self.0.iter() {
+            seq.serialize_element(&tx.map_err(serde::ser::Error::custom)?)?;
+        }
+        seq.end()
+    }
+}
+
+/// Reads the snapshot into the engine as it's parsed, so the transactions go straight
+/// to the storage instead of being collected in memory first. The version is the first
+/// field, it's checked before the rest is read, so a snapshot in a different format is
+/// reported as a version mismatch instead of a confusing deserialization error.
+struct SnapshotSeed<'a> {
+    engine: &'a mut TxEngine,
+    /// Failure that stopped the parsing, reported instead of the deserialization error
+    failure: &'a mut Option<SnapshotError>,
+}
+
+fn fail<E: de::Error>(failure: &mut Option<SnapshotError>, error: SnapshotError) -> E {
+    let message = E::custom(&error);
+    *failure = Some(error);
+    message
+}
+
+impl<'de> DeserializeSeed<'de> for SnapshotSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SnapshotSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an engine snapshot")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        if map.next_key::<String>()?.as_deref() != Some("version") {
+            return Err(fail(
+                self.failure,
+                SnapshotError::Corrupted("version is not the first field"),
+            ));
+        }
+        let version: u32 = map.next_value()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(fail(
+                self.failure,
+                SnapshotError::VersionMismatch {
+                    expected: SNAPSHOT_VERSION,
+                    found: version,
+                },
+            ));
+        }
+
+        let (mut ledger, mut postings, mut committed, mut activity) = (None, None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "clients" => map.next_value_seed(ClientsSeed(&mut *self.engine))?,
+                "transactions" => map.next_value_seed(TransactionsSeed {
+                    engine: &mut *self.engine,
+                    failure: &mut *self.failure,
+                })?,
+                "ledger" => ledger = Some(map.next_value()?),
+                "postings" => postings = Some(map.next_value()?),
+                "committed" => committed = Some(map.next_value()?),
+                "clock" => self.engine.clock = map.next_value()?,
+                "activity" => activity = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        self.engine.ledger = ledger.ok_or_else(|| de::Error::missing_field("ledger"))?;
+        self.engine.postings = postings.ok_or_else(|| de::Error::missing_field("postings"))?;
+        self.engine.committed = committed.ok_or_else(|| de::Error::missing_field("committed"))?;
+        self.engine.activity = activity.ok_or_else(|| de::Error::missing_field("activity"))?;
+        Ok(())
+    }
+}
+
+/// Puts each client into the engine as it's parsed
+struct ClientsSeed<'a>(&'a mut TxEngine);
+
+impl<'de> DeserializeSeed<'de> for ClientsSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ClientsSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of clients")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(client) = seq.next_element::<Client>()? {
+            self.0.clients_store.put(client);
+        }
+        Ok(())
+    }
+}
+
+/// Inserts each transaction into the storage of the engine as it's parsed
+struct TransactionsSeed<'a> {
+    engine: &'a mut TxEngine,
+    failure: &'a mut Option<SnapshotError>,
+}
+
+impl<'de> DeserializeSeed<'de> for TransactionsSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TransactionsSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of transactions")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(tx) = seq.next_element::<Transaction>()? {
+            self.engine.committed_txs.insert(tx).map_err(|e| {
+                let error = match e {
+                    ProcessingError::InvalidTransaction(
+                        TransactionError::DuplicateTransactionId,
+                    ) => SnapshotError::Corrupted("duplicate transaction id"),
+                    e => SnapshotError::Io(std::io::Error::other(e)),
+                };
+                fail(self.failure, error)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl TxEngine {
+    /// Writes the whole engine state - clients with their balances and locks,
+    /// committed transactions with their dispute state, the external ledger accounts,
+    /// the posting log, the counters the dispute window is measured by and the activity
+    /// of the clients the risk rules are evaluated against
+    pub fn save_snapshot(&self, writer: impl Write) -> Result<(), SnapshotError> {
+        let snapshot = SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            clients: self.clients_store.iter().collect(),
+            transactions: Transactions(&self.committed_txs),
+            ledger: &self.ledger,
+            postings: &self.postings,
+            committed: self.committed,
+            clock: self.clock,
+            activity: &self.activity,
+        };
+        serde_json::to_writer(writer, &snapshot)?;
+        Ok(())
+    }
+
+    /// Restores the state from a snapshot into an empty engine, the processing can be continued
+    /// with new transactions. The engine keeps its options and transaction storage.
+    /// The snapshot is parsed as a stream, each transaction goes straight to the storage,
+    /// so a spill file bounds the memory used by a large snapshot too.
+    pub fn restore_snapshot(mut self, reader: impl Read) -> Result<TxEngine, SnapshotError> {
+        let mut failure = None;
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let seed = SnapshotSeed {
+            engine: &mut self,
+            failure: &mut failure,
+        };
+        let result = seed
+            .deserialize(&mut deserializer)
+            .and_then(|()| deserializer.end());
+        if let Some(failure) = failure {
+            return Err(failure);
+        }
+        result.map_err(|e| {
+            if e.is_io() {
+                SnapshotError::Io(e.into())
+            } else {
+                SnapshotError::Format(e)
+            }
+        })?;
+
+        self.check_ledger()
+            .map_err(|_| SnapshotError::Corrupted("ledger is out of balance"))?;
+        Ok(self)
+    }
+}
@@ -1,4 +1,14 @@
-use crate::errors::TransactionError;
+use crate::{
+    currency::Currency,
+    errors::{JournalError, SnapshotError, TransactionError},
+};
+
+use super::{
+    ledger::{Account, Posting},
+    rules::WithdrawalVelocity,
+};
+
+use test_case::test_case;
 
 use super::*;
 
@@ -8,22 +18,22 @@ use utils::*;
 fn test_deposit() {
     let mut engine = TxEngine::default();
 
-    deposit(&mut engine, 1, 100.1111, 1).unwrap();
+    deposit(&mut engine, 1, amount("100.1111"), 1).unwrap();
 
-    let client = engine.get_clients().next().unwrap();
-    assert_eq!(client.available(), 100.1111);
-    assert_eq!(client.held(), 0.0);
-    assert_eq!(client.total(), 100.1111);
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("100.1111"));
+    assert_eq!(client.held(), amount("0.0"));
+    assert_eq!(client.total(), amount("100.1111"));
 }
 
 #[test]
 fn test_withdrawal_more_than_available() {
     let mut engine = TxEngine::default();
 
-    deposit(&mut engine, 1, 100.0, 1).unwrap();
+    deposit(&mut engine, 1, amount("100.0"), 1).unwrap();
 
     assert_eq!(
-        withdrawal(&mut engine, 1, 200.0, 2).unwrap_err(),
+        withdrawal(&mut engine, 1, amount("200.0"), 2).unwrap_err(),
         ProcessingError::InsufficientFunds
     );
 }
@@ -32,7 +42,7 @@ fn test_withdrawal_more_than_available() {
 fn test_client_id_mismatch() {
     let mut engine = TxEngine::default();
 
-    deposit(&mut engine, 1, 100.0, 1).unwrap();
+    deposit(&mut engine, 1, amount("100.0"), 1).unwrap();
 
     assert_eq!(
         dispute(&mut engine, 2, 1).unwrap_err(),
@@ -54,7 +64,7 @@ fn test_disputes_transaction_not_found() {
 fn test_disputes_transaction_already_disputed() {
     let mut engine = TxEngine::default();
 
-    deposit(&mut engine, 1, 100.0, 1).unwrap();
+    deposit(&mut engine, 1, amount("100.0"), 1).unwrap();
     dispute(&mut engine, 1, 1).unwrap();
 
     assert_eq!(
@@ -67,21 +77,21 @@ fn test_disputes_transaction_already_disputed() {
 fn test_resolve_disputed_transaction() {
     let mut engine = TxEngine::default();
 
-    deposit(&mut engine, 1, 100.0, 1).unwrap();
+    deposit(&mut engine, 1, amount("100.0"), 1).unwrap();
 
     dispute(&mut engine, 1, 1).unwrap();
     resolve(&mut engine, 1, 1).unwrap();
 
-    let client = engine.get_clients().next().unwrap();
-    assert_eq!(client.available(), 100.0);
-    assert_eq!(client.held(), 0.0);
-    assert_eq!(client.total(), 100.0);
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("100.0"));
+    assert_eq!(client.held(), amount("0.0"));
+    assert_eq!(client.total(), amount("100.0"));
 }
 
 #[test]
 fn test_resolved_transaction_cannot_be_disputed_again() {
     let mut engine = TxEngine::default();
-    deposit(&mut engine, 1, 100.0, 1).unwrap();
+    deposit(&mut engine, 1, amount("100.0"), 1).unwrap();
     dispute(&mut engine, 1, 1).unwrap();
     resolve(&mut engine, 1, 1).unwrap();
 
@@ -95,14 +105,14 @@ fn test_resolved_transaction_cannot_be_disputed_again() {
 fn test_chargeback_disputed_transaction() {
     let mut engine = TxEngine::default();
 
-    deposit(&mut engine, 1, 100.0, 1).unwrap();
+    deposit(&mut engine, 1, amount("100.0"), 1).unwrap();
     dispute(&mut engine, 1, 1).unwrap();
     chargeback(&mut engine, 1, 1).unwrap();
 
-    let client = engine.get_clients().next().unwrap();
-    assert_eq!(client.available(), 0.0);
-    assert_eq!(client.held(), 0.0);
-    assert_eq!(client.total(), 0.0);
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("0.0"));
+    assert_eq!(client.held(), amount("0.0"));
+    assert_eq!(client.total(), amount("0.0"));
     assert!(client.is_locked());
 }
 
@@ -110,12 +120,12 @@ fn test_chargeback_disputed_transaction() {
 fn test_withdrawal_after_chargeback() {
     let mut engine = TxEngine::default();
 
-    deposit(&mut engine, 1, 200.0, 1).unwrap();
+    deposit(&mut engine, 1, amount("200.0"), 1).unwrap();
     dispute(&mut engine, 1, 1).unwrap();
     chargeback(&mut engine, 1, 1).unwrap();
 
     assert_eq!(
-        withdrawal(&mut engine, 1, 100.0, 2).unwrap_err(),
+        withdrawal(&mut engine, 1, amount("100.0"), 2).unwrap_err(),
         ProcessingError::ClientLocked
     );
 }
@@ -124,60 +134,1459 @@ fn test_withdrawal_after_chargeback() {
 fn test_negative_balance_after_dispute() {
     let mut engine = TxEngine::default();
 
-    deposit(&mut engine, 1, 100.0, 1).unwrap();
-    withdrawal(&mut engine, 1, 50.0, 2).unwrap();
+    deposit(&mut engine, 1, amount("100.0"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("50.0"), 2).unwrap();
 
-    let client = engine.get_clients().next().unwrap();
-    assert_eq!(client.available(), 50.0);
-    assert_eq!(client.held(), 0.0);
-    assert_eq!(client.total(), 50.0);
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("50.0"));
+    assert_eq!(client.held(), amount("0.0"));
+    assert_eq!(client.total(), amount("50.0"));
 
     dispute(&mut engine, 1, 1).unwrap();
-    let client = engine.get_clients().next().unwrap();
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
 
     // is this correct ?
-    assert_eq!(client.available(), -50.0);
-    assert_eq!(client.held(), 100.0);
-    assert_eq!(client.total(), 50.0);
+    assert_eq!(client.available(), amount("-50.0"));
+    assert_eq!(client.held(), amount("100.0"));
+    assert_eq!(client.total(), amount("50.0"));
 
     chargeback(&mut engine, 1, 1).unwrap();
 
-    let client = engine.get_clients().next().unwrap();
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
 
     // is this correct ?
-    assert_eq!(client.available(), -50.0);
-    assert_eq!(client.held(), 0.0);
-    assert_eq!(client.total(), -50.0);
+    assert_eq!(client.available(), amount("-50.0"));
+    assert_eq!(client.held(), amount("0.0"));
+    assert_eq!(client.total(), amount("-50.0"));
+}
+
+#[test_case(true; "deposit")]
+#[test_case(false; "withdrawal")]
+fn test_duplicate_transaction_id_is_rejected(is_deposit: bool) {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+
+    let result = if is_deposit {
+        deposit(&mut engine, 1, amount("50"), 1)
+    } else {
+        withdrawal(&mut engine, 1, amount("50"), 1)
+    };
+    assert_eq!(
+        result.unwrap_err(),
+        ProcessingError::InvalidTransaction(TransactionError::DuplicateTransactionId)
+    );
+
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("100"));
+    assert_eq!(client.total(), amount("100"));
+}
+
+#[test]
+fn test_duplicate_transaction_id_keeps_dispute_state() {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    dispute(&mut engine, 1, 1).unwrap();
+    deposit(&mut engine, 1, amount("100"), 1).unwrap_err();
+
+    // The original transaction is still under dispute, so it can be resolved
+    resolve(&mut engine, 1, 1).unwrap();
+
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("100"));
+    assert_eq!(client.held(), amount("0"));
+    assert_eq!(client.total(), amount("100"));
+}
+
+#[test]
+fn test_failed_withdrawal_does_not_use_transaction_id() {
+    let mut engine = TxEngine::default();
+
+    withdrawal(&mut engine, 1, amount("50"), 1).unwrap_err();
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("100"));
+}
+
+#[test]
+fn test_withdrawal_dispute_rejected_by_policy() {
+    let mut engine = TxEngine::new(EngineOptions {
+        withdrawal_dispute_policy: WithdrawalDisputePolicy::Reject,
+        ..Default::default()
+    });
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("40"), 2).unwrap();
+
+    assert_eq!(
+        dispute(&mut engine, 1, 2).unwrap_err(),
+        ProcessingError::InvalidTransaction(TransactionError::WithdrawalNotDisputable)
+    );
+    // Deposits are still disputable
+    dispute(&mut engine, 1, 1).unwrap();
+}
+
+#[test]
+fn test_withdrawal_dispute_reversed_by_policy() {
+    let mut engine = TxEngine::new(EngineOptions {
+        withdrawal_dispute_policy: WithdrawalDisputePolicy::Reverse,
+        ..Default::default()
+    });
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("40"), 2).unwrap();
+    dispute(&mut engine, 1, 2).unwrap();
+
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("60"));
+    assert_eq!(client.held(), amount("40"));
+    assert_eq!(client.total(), amount("100"));
+
+    chargeback(&mut engine, 1, 2).unwrap();
+
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("100"));
+    assert_eq!(client.held(), amount("0"));
+    assert_eq!(client.total(), amount("100"));
+    assert!(client.is_locked());
+}
+
+#[test]
+fn test_unlock_reinstates_account() {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    dispute(&mut engine, 1, 1).unwrap();
+    chargeback(&mut engine, 1, 1).unwrap();
+    assert_eq!(
+        deposit(&mut engine, 1, amount("10"), 2).unwrap_err(),
+        ProcessingError::ClientLocked
+    );
+
+    unlock(&mut engine, 1, 3).unwrap();
+    deposit(&mut engine, 1, amount("10"), 2).unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.balance(Currency::EUR).available(), amount("10"));
+    assert!(!client.is_locked());
+    // The id of an unlock is not reserved
+    assert_eq!(engine.get_transaction(3).unwrap().map(|tx| tx.id()), None);
+}
+
+#[test]
+fn test_unlock_of_unlocked_client_is_rejected() {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+
+    assert_eq!(
+        unlock(&mut engine, 1, 2).unwrap_err(),
+        ProcessingError::ClientNotLocked
+    );
+    assert_eq!(
+        unlock(&mut engine, 2, 3).unwrap_err(),
+        ProcessingError::ClientNotLocked
+    );
+    assert!(engine.get_client(2).is_none());
+}
+
+#[test_case(LockedAccountPolicy::RejectAll, false; "reject all")]
+#[test_case(LockedAccountPolicy::SettleDisputes, true; "settle disputes")]
+fn test_locked_account_policy(policy: LockedAccountPolicy, settles: bool) {
+    let mut engine = TxEngine::new(EngineOptions {
+        locked_account_policy: policy,
+        ..Default::default()
+    });
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    deposit(&mut engine, 1, amount("50"), 2).unwrap();
+    deposit(&mut engine, 1, amount("20"), 3).unwrap();
+    dispute(&mut engine, 1, 1).unwrap();
+    dispute(&mut engine, 1, 2).unwrap();
+    chargeback(&mut engine, 1, 1).unwrap();
+
+    // Settlements of the disputes opened before the lock depend on the policy
+    assert_eq!(resolve(&mut engine, 1, 2).is_ok(), settles);
+    // Other operations are always rejected
+    for result in [
+        deposit(&mut engine, 1, amount("10"), 4),
+        withdrawal(&mut engine, 1, amount("10"), 5),
+        dispute(&mut engine, 1, 3),
+    ] {
+        assert_eq!(result.unwrap_err(), ProcessingError::ClientLocked);
+    }
+
+    let client = engine.get_client(1).unwrap();
+    let held = if settles { "0" } else { "50" };
+    assert_eq!(client.balance(Currency::EUR).held(), amount(held));
+    assert!(client.is_locked());
+}
+
+#[test]
+fn test_dispute_window_in_transactions() {
+    let mut engine = TxEngine::new(EngineOptions {
+        dispute_window: Some(DisputeWindow::Transactions(1)),
+        ..Default::default()
+    });
+
+    deposit(&mut engine, 1, amount("10"), 1).unwrap();
+    deposit(&mut engine, 2, amount("10"), 2).unwrap();
+    // Rejected records and disputes don't move the window
+    withdrawal(&mut engine, 1, amount("100"), 3).unwrap_err();
+    dispute(&mut engine, 2, 2).unwrap();
+    dispute(&mut engine, 1, 1).unwrap();
+    resolve(&mut engine, 1, 1).unwrap();
+
+    deposit(&mut engine, 1, amount("10"), 4).unwrap();
+    assert_eq!(
+        dispute(&mut engine, 1, 1).unwrap_err(),
+        ProcessingError::InvalidTransaction(TransactionError::DisputeWindowExpired)
+    );
+    // Settlement of a dispute opened in the window is not limited
+    resolve(&mut engine, 2, 2).unwrap();
+}
+
+#[test]
+fn test_dispute_window_of_transaction_ahead_of_count() {
+    // A restored state may hold a sequence the count hasn't reached, it is in the window
+    let tx = Transaction::new(
+        1,
+        TransactionKind::Deposit,
+        amount("10"),
+        Currency::EUR,
+        1,
+        5,
+        None,
+    );
+    assert!(!DisputeWindow::Transactions(0).is_expired(&tx, 3, None));
+    assert!(!DisputeWindow::Transactions(0).is_expired(&tx, 6, None));
+    assert!(DisputeWindow::Transactions(0).is_expired(&tx, 7, None));
+}
+
+#[test]
+fn test_dispute_window_in_seconds() {
+    let mut engine = TxEngine::new(EngineOptions {
+        dispute_window: Some(DisputeWindow::Seconds(60)),
+        ..Default::default()
+    });
+    deposit_at(&mut engine, 1, Some(1000)).unwrap();
+    deposit_at(&mut engine, 2, Some(1030)).unwrap();
+    deposit_at(&mut engine, 3, None).unwrap();
+
+    dispute_at(&mut engine, 2, Some(1090)).unwrap();
+    let expired = ProcessingError::InvalidTransaction(TransactionError::DisputeWindowExpired);
+    assert_eq!(dispute_at(&mut engine, 1, Some(1061)).unwrap_err(), expired);
+    // Without its own timestamp, a dispute is timed by the latest accepted record
+    assert_eq!(dispute_at(&mut engine, 1, None).unwrap_err(), expired);
+    // A transaction without a timestamp never expires
+    dispute_at(&mut engine, 3, Some(5000)).unwrap();
+}
+
+#[test_case(OutOfOrderPolicy::Reject; "reject")]
+#[test_case(OutOfOrderPolicy::Resequence; "resequence")]
+fn test_out_of_order_rejected(policy: OutOfOrderPolicy) {
+    let mut engine = TxEngine::new(EngineOptions {
+        out_of_order_policy: policy,
+        ..Default::default()
+    });
+    deposit_at(&mut engine, 1, Some(1000)).unwrap();
+    deposit_at(&mut engine, 2, Some(1000)).unwrap();
+    assert_eq!(
+        deposit_at(&mut engine, 3, Some(999)).unwrap_err(),
+        ProcessingError::OutOfOrder {
+            latest: Timestamp::from_secs(1000)
+        }
+    );
+    // Records without a timestamp are never out of order
+    deposit_at(&mut engine, 4, None).unwrap();
+    dispute_at(&mut engine, 1, Some(1001)).unwrap();
+    assert_eq!(
+        engine.get_client(1).unwrap().balance(Currency::EUR).total(),
+        amount("30")
+    );
+}
+
+#[test]
+fn test_out_of_order_accepted_by_default() {
+    let mut engine = TxEngine::default();
+    deposit_at(&mut engine, 1, Some(1000)).unwrap();
+    deposit_at(&mut engine, 2, Some(999)).unwrap();
+    assert_eq!(
+        engine.get_client(1).unwrap().balance(Currency::EUR).total(),
+        amount("20")
+    );
+}
+
+#[test]
+fn test_evict_expired_transactions() {
+    let mut engine = TxEngine::new(EngineOptions {
+        dispute_window: Some(DisputeWindow::Transactions(1)),
+        ..Default::default()
+    });
+    deposit(&mut engine, 1, amount("10"), 1).unwrap();
+    deposit(&mut engine, 1, amount("10"), 2).unwrap();
+    deposit(&mut engine, 2, amount("10"), 3).unwrap();
+    dispute(&mut engine, 1, 2).unwrap();
+    deposit(&mut engine, 1, amount("10"), 4).unwrap();
+    deposit(&mut engine, 1, amount("10"), 5).unwrap();
+
+    // Transactions 1 to 3 are expired, 2 is kept while it is under dispute
+    assert_eq!(engine.evict_expired().unwrap(), 2);
+    let ids = |engine: &TxEngine, client| -> Vec<_> {
+        engine
+            .client_transactions(client)
+            .map(|tx| tx.unwrap().id())
+            .collect()
+    };
+    assert_eq!(ids(&engine, 1), [2, 4, 5]);
+    assert!(ids(&engine, 2).is_empty());
+    assert!(engine.get_transaction(1).unwrap().is_none());
+
+    resolve(&mut engine, 1, 2).unwrap();
+    // Ids of the evicted transactions can be reused, the new ones are appended to the index
+    deposit(&mut engine, 1, amount("10"), 1).unwrap();
+    assert_eq!(ids(&engine, 1), [2, 4, 5, 1]);
+    assert_eq!(engine.evict_expired().unwrap(), 2);
+    assert_eq!(ids(&engine, 1), [5, 1]);
+    engine.check_ledger().unwrap();
+}
+
+#[test]
+fn test_deposit_overflow_leaves_balances_untouched() {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("900000000000000"), 1).unwrap();
+
+    assert_eq!(
+        deposit(&mut engine, 1, amount("900000000000000"), 2).unwrap_err(),
+        ProcessingError::AmountOverflow
+    );
+
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("900000000000000"));
+    assert_eq!(client.held(), amount("0"));
+    assert_eq!(client.total(), amount("900000000000000"));
+}
+
+#[test]
+fn test_snapshot_resume() {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    deposit(&mut engine, 1, amount("50"), 2).unwrap();
+    dispute(&mut engine, 1, 1).unwrap();
+    deposit(&mut engine, 2, amount("10"), 3).unwrap();
+    dispute(&mut engine, 2, 3).unwrap();
+    chargeback(&mut engine, 2, 3).unwrap();
+
+    let mut snapshot = Vec::new();
+    engine.save_snapshot(&mut snapshot).unwrap();
+    let mut engine = TxEngine::default()
+        .restore_snapshot(snapshot.as_slice())
+        .unwrap();
+
+    // Dispute state, committed ids and locks are restored
+    resolve(&mut engine, 1, 1).unwrap();
+    assert_eq!(
+        deposit(&mut engine, 1, amount("1"), 2).unwrap_err(),
+        ProcessingError::InvalidTransaction(TransactionError::DuplicateTransactionId)
+    );
+    assert_eq!(
+        deposit(&mut engine, 2, amount("1"), 4).unwrap_err(),
+        ProcessingError::ClientLocked
+    );
+
+    let mut clients = engine.get_balances(ClientOrder::Id);
+    let client = clients.next().unwrap();
+    assert_eq!(client.available(), amount("150"));
+    assert_eq!(client.held(), amount("0"));
+    assert_eq!(client.total(), amount("150"));
+    let client = clients.next().unwrap();
+    assert_eq!(client.total(), amount("0"));
+    assert!(client.is_locked());
+}
+
+#[test]
+fn test_snapshot_version_mismatch() {
+    let snapshot = r#"{"version":999,"clients":[],"transactions":[]}"#;
+
+    let error = TxEngine::default()
+        .restore_snapshot(snapshot.as_bytes())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        SnapshotError::VersionMismatch {
+            expected: snapshot::SNAPSHOT_VERSION,
+            found: 999
+        }
+    ));
+
+    // A snapshot is never written without the version first
+    let snapshot = r#"{"clients":[],"version":999}"#;
+    let error = TxEngine::default()
+        .restore_snapshot(snapshot.as_bytes())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        SnapshotError::Corrupted("version is not the first field")
+    ));
+}
+
+#[test]
+fn test_snapshot_with_tampered_balances_is_rejected() {
+    let mut engine = TxEngine::default();
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    let mut snapshot = Vec::new();
+    engine.save_snapshot(&mut snapshot).unwrap();
+    let snapshot = String::from_utf8(snapshot).unwrap();
+
+    // The total does not match the available and held balances
+    let tampered = snapshot.replace(r#""total":"100""#, r#""total":"200""#);
+    assert_ne!(tampered, snapshot);
+    let error = TxEngine::default()
+        .restore_snapshot(tampered.as_bytes())
+        .err()
+        .unwrap();
+    assert!(matches!(error, SnapshotError::Format(_)));
+
+    // Funds appeared without a posting from an external account
+    let tampered = snapshot
+        .replace(r#""available":"100""#, r#""available":"150""#)
+        .replace(r#""total":"100""#, r#""total":"150""#);
+    let error = TxEngine::default()
+        .restore_snapshot(tampered.as_bytes())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        SnapshotError::Corrupted("ledger is out of balance")
+    ));
+
+    // The ledger is balanced, but the funds are not in the posting log
+    let balanced = tampered.replace(r#""EUR":"-100""#, r#""EUR":"-150""#);
+    assert_ne!(balanced, tampered);
+    let error = TxEngine::default()
+        .restore_snapshot(balanced.as_bytes())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        SnapshotError::Corrupted("ledger is out of balance")
+    ));
+}
+
+#[test]
+fn test_commit_breaking_ledger_is_rejected() {
+    let mut engine = TxEngine::default();
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+
+    // The funds of the deposit come from nowhere without its posting
+    let mut prepared = engine
+        .prepare_tx(&deposit_record(1, amount("50"), 2))
+        .unwrap();
+    prepared.ledger = engine.ledger.clone();
+    assert!(matches!(
+        engine.commit_tx(prepared).unwrap_err(),
+        ProcessingError::Ledger(_)
+    ));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.balance(Currency::EUR).total(), amount("100"));
+    assert_eq!(engine.get_transaction(2).unwrap().map(|tx| tx.id()), None);
+    engine.check_ledger().unwrap();
+}
+
+#[test]
+fn test_ledger_check_resums_the_posting_log() {
+    let mut engine = TxEngine::default();
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("30"), 2).unwrap();
+    engine.check_ledger().unwrap();
+
+    // A balanced posting, which was never applied to the balances
+    engine.postings.append(Posting::new(
+        Account::Settlement,
+        Account::Chargebacks,
+        amount("5"),
+        Currency::EUR,
+    ));
+    assert!(matches!(
+        engine.check_ledger().unwrap_err(),
+        LedgerError::LogMismatch(account) if account == "settlement account in EUR"
+    ));
+}
+
+#[test]
+fn test_ledger_postings() {
+    let mut client = Client::new(1);
+    let policy = LockedAccountPolicy::default();
+    let value = amount("10");
+    let usd = Currency::USD;
+    let posting = |from, to| Posting::new(from, to, value, usd);
+
+    assert_eq!(
+        client.deposit(value, usd).unwrap(),
+        posting(Account::Settlement, Account::Available(1))
+    );
+    assert_eq!(
+        client.dispute(value, usd).unwrap(),
+        posting(Account::Available(1), Account::Held(1))
+    );
+    assert_eq!(
+        client.resolve(value, usd, policy).unwrap(),
+        posting(Account::Held(1), Account::Available(1))
+    );
+    assert_eq!(
+        client.withdraw(value, usd, Amount::ZERO).unwrap(),
+        posting(Account::Available(1), Account::Settlement)
+    );
+    assert_eq!(
+        client.dispute_withdrawal(value, usd).unwrap(),
+        posting(Account::Chargebacks, Account::Held(1))
+    );
+    assert_eq!(
+        client.resolve_withdrawal(value, usd, policy).unwrap(),
+        posting(Account::Held(1), Account::Chargebacks)
+    );
+    client.dispute_withdrawal(value, usd).unwrap();
+    assert_eq!(
+        client.charge_back_withdrawal(value, usd, policy).unwrap(),
+        posting(Account::Held(1), Account::Available(1))
+    );
+    client.unlock().unwrap();
+    client.dispute(value, usd).unwrap();
+    assert_eq!(
+        client.charge_back(value, usd, policy).unwrap(),
+        posting(Account::Held(1), Account::Chargebacks)
+    );
+}
+
+#[test_case(WithdrawalDisputePolicy::Legacy; "legacy withdrawal disputes")]
+#[test_case(WithdrawalDisputePolicy::Reverse; "reversed withdrawal disputes")]
+fn test_ledger_stays_balanced(withdrawal_dispute_policy: WithdrawalDisputePolicy) {
+    let options = EngineOptions {
+        withdrawal_dispute_policy,
+        ..Default::default()
+    };
+    let records = random_records(5000);
+
+    let mut engine = TxEngine::new(options.clone());
+    for record in records.iter().cloned() {
+        let _ = engine.process_tx(record);
+        engine.check_ledger().unwrap();
+    }
+
+    let mut sharded = ShardedEngine::new(TxEngine::new(options), 4).unwrap();
+    records
+        .iter()
+        .enumerate()
+        .for_each(|(i, tx)| sharded.process_tx(tx.clone(), i));
+    let (mut merged, _) = sharded.finish().unwrap();
+    merged.check_ledger().unwrap();
+    assert_eq!(merged.ledger, engine.ledger);
+}
+
+#[test]
+fn test_balances_are_kept_per_currency() {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("10"), 1).unwrap();
+    record_in(
+        &mut engine,
+        deposit_record(1, amount("5"), 2),
+        Currency::USD,
+    )
+    .unwrap();
+
+    assert_eq!(
+        record_in(
+            &mut engine,
+            withdrawal_record(1, amount("6"), 3),
+            Currency::USD
+        )
+        .unwrap_err(),
+        ProcessingError::InsufficientFunds
+    );
+    record_in(
+        &mut engine,
+        withdrawal_record(1, amount("5"), 3),
+        Currency::USD,
+    )
+    .unwrap();
+
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.balance(Currency::EUR).available(), amount("10"));
+    assert_eq!(client.balance(Currency::USD).available(), amount("0"));
+    let currencies: Vec<_> = engine
+        .get_balances(ClientOrder::Id)
+        .map(|balance| balance.currency())
+        .collect();
+    assert_eq!(currencies, [Currency::EUR, Currency::USD]);
+    engine.check_ledger().unwrap();
+}
+
+#[test]
+fn test_unknown_currency_is_rejected() {
+    let mut engine = TxEngine::default();
+    let chf = "CHF".parse().unwrap();
+
+    assert_eq!(
+        record_in(&mut engine, deposit_record(1, amount("10"), 1), chf).unwrap_err(),
+        ProcessingError::UnknownCurrency(chf)
+    );
+    assert!(engine.get_client(1).is_none());
+}
+
+#[test]
+fn test_dispute_in_other_currency_is_rejected() {
+    let mut engine = TxEngine::default();
+
+    record_in(
+        &mut engine,
+        deposit_record(1, amount("10"), 1),
+        Currency::USD,
+    )
+    .unwrap();
+
+    let dispute = TransactionRecord {
+        tx_type: TransactionRecordType::Dispute,
+        client: 1,
+        tx: 1,
+        timestamp: None,
+        currency: None,
+    };
+    assert_eq!(
+        record_in(&mut engine, dispute.clone(), Currency::GBP).unwrap_err(),
+        ProcessingError::InvalidTransaction(TransactionError::CurrencyMismatch)
+    );
+    // Without a currency the dispute is in the currency of the disputed transaction
+    engine.process_tx(dispute).unwrap();
+    assert_eq!(
+        engine.get_client(1).unwrap().balance(Currency::USD).held(),
+        amount("10")
+    );
+}
+
+#[test]
+fn test_withdrawal_within_credit_limit() {
+    let options = EngineOptions {
+        credit_limits: [((1, Currency::EUR), amount("50"))].into(),
+        ..Default::default()
+    };
+    let mut engine = TxEngine::new(options);
+
+    deposit(&mut engine, 1, amount("10"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("40"), 2).unwrap();
+    assert_eq!(
+        withdrawal(&mut engine, 1, amount("20.0001"), 3).unwrap_err(),
+        ProcessingError::CreditLimitExceeded(amount("50"))
+    );
+    withdrawal(&mut engine, 1, amount("20"), 3).unwrap();
+
+    let balance = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(balance.available(), amount("-50"));
+    assert!(balance.is_overdrawn());
+    engine.check_ledger().unwrap();
+
+    // Limits are per client and currency
+    deposit(&mut engine, 2, amount("10"), 4).unwrap();
+    assert_eq!(
+        withdrawal(&mut engine, 2, amount("11"), 5).unwrap_err(),
+        ProcessingError::InsufficientFunds
+    );
+}
+
+#[test]
+fn test_limit_record_overrides_configured_limit() {
+    let options = EngineOptions {
+        credit_limits: [((1, Currency::EUR), amount("50"))].into(),
+        ..Default::default()
+    };
+    let mut engine = TxEngine::new(options.clone());
+
+    limit(&mut engine, 1, Some(amount("100"))).unwrap();
+    withdrawal(&mut engine, 1, amount("80"), 1).unwrap();
+
+    // The override is kept by snapshots, the configured limit still comes from the options
+    let mut snapshot = Vec::new();
+    engine.save_snapshot(&mut snapshot).unwrap();
+    let mut engine = TxEngine::new(options)
+        .restore_snapshot(snapshot.as_slice())
+        .unwrap();
+    withdrawal(&mut engine, 1, amount("20"), 2).unwrap();
+
+    // Lowering the limit leaves the account overdrawn, a revoked limit allows no overdraft
+    limit(&mut engine, 1, None).unwrap();
+    assert_eq!(
+        withdrawal(&mut engine, 1, amount("1"), 3).unwrap_err(),
+        ProcessingError::InsufficientFunds
+    );
+    deposit(&mut engine, 1, amount("30"), 4).unwrap();
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(client.balance(Currency::EUR).available(), amount("-70"));
+    assert_eq!(client.credit_limit(Currency::EUR), Some(amount("0")));
+}
+
+#[test]
+fn test_limit_record_on_locked_account() {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("10"), 1).unwrap();
+    dispute(&mut engine, 1, 1).unwrap();
+    chargeback(&mut engine, 1, 1).unwrap();
+
+    limit(&mut engine, 1, Some(amount("5"))).unwrap();
+    assert_eq!(
+        withdrawal(&mut engine, 1, amount("1"), 2).unwrap_err(),
+        ProcessingError::ClientLocked
+    );
+    unlock(&mut engine, 1, 3).unwrap();
+    withdrawal(&mut engine, 1, amount("5"), 2).unwrap();
+}
+
+#[test]
+fn test_deny_list_rejects_all_records() {
+    let options = EngineOptions {
+        risk_rules: RiskRules {
+            deny_list: [2].into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut engine = TxEngine::new(options);
+
+    deposit(&mut engine, 1, amount("10"), 1).unwrap();
+    assert!(matches!(
+        deposit(&mut engine, 2, amount("10"), 2).unwrap_err(),
+        ProcessingError::RuleViolation {
+            rule: RiskRule::DenyList,
+            ..
+        }
+    ));
+    assert!(engine.get_client(2).is_none());
+    // The id of the rejected record is not used
+    deposit(&mut engine, 1, amount("10"), 2).unwrap();
+}
+
+#[test_case(RiskRule::MaxWithdrawal, "rule_violation:max_withdrawal"; "max withdrawal")]
+#[test_case(RiskRule::WithdrawalVelocity, "rule_violation:withdrawal_velocity"; "withdrawal velocity")]
+#[test_case(RiskRule::MaxDisputes, "rule_violation:max_disputes"; "max disputes")]
+#[test_case(RiskRule::DenyList, "rule_violation:deny_list"; "deny list")]
+fn test_rule_violation_code(rule: RiskRule, expected: &str) {
+    let error = ProcessingError::RuleViolation {
+        rule,
+        detail: String::new(),
+    };
+    assert_eq!(error.code(), expected);
+}
+
+#[test]
+fn test_max_withdrawal_rule() {
+    let options = EngineOptions {
+        risk_rules: RiskRules {
+            max_withdrawal: [(Currency::EUR, amount("50"))].into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut engine = TxEngine::new(options);
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("50"), 2).unwrap();
+    assert_eq!(
+        withdrawal(&mut engine, 1, amount("50.0001"), 3).unwrap_err(),
+        ProcessingError::RuleViolation {
+            rule: RiskRule::MaxWithdrawal,
+            detail: "50.0001 EUR is over the maximum of 50 EUR".to_string(),
+        }
+    );
+
+    // Withdrawals in the other currencies are not limited
+    record_in(
+        &mut engine,
+        deposit_record(1, amount("100"), 4),
+        Currency::USD,
+    )
+    .unwrap();
+    record_in(
+        &mut engine,
+        withdrawal_record(1, amount("100"), 5),
+        Currency::USD,
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_withdrawal_velocity_rule() {
+    let options = EngineOptions {
+        risk_rules: RiskRules {
+            withdrawal_velocity: Some(WithdrawalVelocity {
+                max_withdrawals: 2,
+                records: 3.try_into().unwrap(),
+            }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut engine = TxEngine::new(options);
+
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("1"), 2).unwrap();
+    withdrawal(&mut engine, 1, amount("1"), 3).unwrap();
+    assert!(matches!(
+        withdrawal(&mut engine, 1, amount("1"), 4).unwrap_err(),
+        ProcessingError::RuleViolation {
+            rule: RiskRule::WithdrawalVelocity,
+            ..
+        }
+    ));
+    // Other clients have their own window
+    deposit(&mut engine, 2, amount("100"), 4).unwrap();
+    withdrawal(&mut engine, 2, amount("1"), 5).unwrap();
+
+    // The rejected withdrawal isn't counted, one more record moves the window
+    deposit(&mut engine, 1, amount("1"), 6).unwrap();
+    withdrawal(&mut engine, 1, amount("1"), 7).unwrap();
+    withdrawal(&mut engine, 1, amount("1"), 8).unwrap();
+    assert!(withdrawal(&mut engine, 1, amount("1"), 9).is_err());
+}
+
+#[test]
+fn test_max_disputes_locks_client() {
+    let options = EngineOptions {
+        risk_rules: RiskRules {
+            max_disputes: Some(2),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut engine = TxEngine::new(options.clone());
+
+    for tx in 1..=4 {
+        deposit(&mut engine, 1, amount("10"), tx).unwrap();
+    }
+    dispute(&mut engine, 1, 1).unwrap();
+    assert!(!engine.get_client(1).unwrap().is_locked());
+    dispute(&mut engine, 1, 2).unwrap();
+    assert!(engine.get_client(1).unwrap().is_locked());
+    assert!(matches!(
+        dispute(&mut engine, 1, 3).unwrap_err(),
+        ProcessingError::RuleViolation {
+            rule: RiskRule::MaxDisputes,
+            ..
+        }
+    ));
+
+    // The activity is kept by snapshots, unlocking starts the count again
+    let mut snapshot = Vec::new();
+    engine.save_snapshot(&mut snapshot).unwrap();
+    let mut engine = TxEngine::new(options)
+        .restore_snapshot(snapshot.as_slice())
+        .unwrap();
+    unlock(&mut engine, 1, 5).unwrap();
+    dispute(&mut engine, 1, 3).unwrap();
+    assert!(!engine.get_client(1).unwrap().is_locked());
+    engine.check_ledger().unwrap();
+}
+
+#[test]
+fn test_client_transaction_index() {
+    let mut engine = TxEngine::default();
+
+    deposit(&mut engine, 1, amount("100"), 7).unwrap();
+    deposit(&mut engine, 2, amount("10"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("20"), 3).unwrap();
+    withdrawal(&mut engine, 1, amount("500"), 4).unwrap_err();
+    deposit(&mut engine, 1, amount("5"), 2).unwrap();
+    dispute(&mut engine, 1, 7).unwrap();
+    dispute(&mut engine, 1, 2).unwrap();
+    chargeback(&mut engine, 1, 2).unwrap();
+
+    let history = |engine: &TxEngine| -> Vec<_> {
+        engine
+            .client_transactions(1)
+            .map(|tx| tx.unwrap())
+            .map(|tx| (tx.id(), tx.kind(), tx.state()))
+            .collect()
+    };
+    let expected = [
+        (7, TransactionKind::Deposit, TransactionState::Disputed),
+        (3, TransactionKind::Withdrawal, TransactionState::Committed),
+        (2, TransactionKind::Deposit, TransactionState::ChargedBack),
+    ];
+    assert_eq!(history(&engine), expected);
+    assert_eq!(engine.client_transactions(3).count(), 0);
+
+    // The order survives a snapshot
+    let mut snapshot = Vec::new();
+    engine.save_snapshot(&mut snapshot).unwrap();
+    let engine = TxEngine::default()
+        .restore_snapshot(snapshot.as_slice())
+        .unwrap();
+    assert_eq!(history(&engine), expected);
+}
+
+#[test]
+fn test_disk_storage_matches_memory() {
+    let path = std::env::temp_dir().join("transactions_test_disk_storage.bin");
+    let records = random_records(5000);
+
+    let mut memory = TxEngine::default();
+    let memory_errors: Vec<_> = records
+        .iter()
+        .map(|tx| memory.process_tx(tx.clone()).err())
+        .collect();
+
+    // The cache holds a fraction of the transactions, most of them are read back from the file
+    let storage = DiskStorage::create(&path, 64).unwrap();
+    let mut disk = TxEngine::with_storage(EngineOptions::default(), Box::new(storage));
+    let disk_errors: Vec<_> = records
+        .iter()
+        .map(|tx| disk.process_tx(tx.clone()).err())
+        .collect();
+    assert_eq!(disk_errors, memory_errors);
+
+    let history = |engine: &TxEngine, client| -> Vec<_> {
+        engine
+            .client_transactions(client)
+            .map(|tx| tx.unwrap())
+            .map(|tx| (tx.id(), tx.get_amount(), tx.state()))
+            .collect()
+    };
+    for client in 0..20 {
+        assert_eq!(history(&disk, client), history(&memory, client));
+    }
+
+    let mut disk_snapshot = Vec::new();
+    disk.save_snapshot(&mut disk_snapshot).unwrap();
+    let restored = TxEngine::default()
+        .restore_snapshot(disk_snapshot.as_slice())
+        .unwrap();
+    for client in 0..20 {
+        assert_eq!(history(&restored, client), history(&memory, client));
+    }
+    drop(disk);
+    assert!(!path.exists());
+
+    // A snapshot is restored into the spill file as well
+    let storage = DiskStorage::create(&path, 64).unwrap();
+    let restored = TxEngine::with_storage(EngineOptions::default(), Box::new(storage))
+        .restore_snapshot(disk_snapshot.as_slice())
+        .unwrap();
+    for client in 0..20 {
+        assert_eq!(history(&restored, client), history(&memory, client));
+    }
+    drop(restored);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_journal_recovery() {
+    let path = std::env::temp_dir().join("transactions_test_journal_recovery.journal");
+    let _ = std::fs::remove_file(&path);
+
+    let mut engine = TxEngine::default();
+    let (journal, _) = Journal::recover(&path, &mut engine).unwrap();
+    engine.attach_journal(journal);
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    withdrawal(&mut engine, 1, amount("500"), 2).unwrap_err();
+    dispute(&mut engine, 1, 1).unwrap();
+    drop(engine);
+
+    let mut engine = TxEngine::default();
+    let (_, summary) = Journal::recover(&path, &mut engine).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // The rejected withdrawal is not journaled
+    assert_eq!(summary.replayed, 2);
+    assert!(summary.rejected.is_empty());
+    assert_eq!(summary.truncated_bytes, 0);
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.available(), amount("0"));
+    assert_eq!(client.held(), amount("100"));
+}
+
+#[test]
+fn test_journal_torn_record_is_truncated() {
+    let path = std::env::temp_dir().join("transactions_test_journal_torn.journal");
+    let _ = std::fs::remove_file(&path);
+
+    let mut engine = TxEngine::default();
+    let (journal, _) = Journal::recover(&path, &mut engine).unwrap();
+    engine.attach_journal(journal);
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    deposit(&mut engine, 1, amount("50"), 2).unwrap();
+    drop(engine);
+
+    // Simulates a crash in the middle of writing the last entry
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 3).unwrap();
+    drop(file);
+
+    let mut engine = TxEngine::default();
+    let (journal, summary) = Journal::recover(&path, &mut engine).unwrap();
+    assert_eq!(summary.replayed, 1);
+    assert!(summary.truncated_bytes > 0);
+
+    // New entries are appended after the last complete one
+    engine.attach_journal(journal);
+    deposit(&mut engine, 1, amount("25"), 3).unwrap();
+    drop(engine);
+
+    let mut engine = TxEngine::default();
+    let (_, summary) = Journal::recover(&path, &mut engine).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(summary.replayed, 2);
+    assert_eq!(summary.truncated_bytes, 0);
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.total(), amount("125"));
+}
+
+#[test]
+fn test_journal_corruption_in_the_middle_fails() {
+    let path = std::env::temp_dir().join("transactions_test_journal_corrupted.journal");
+    let _ = std::fs::remove_file(&path);
+
+    let mut engine = TxEngine::default();
+    let (journal, _) = Journal::recover(&path, &mut engine).unwrap();
+    engine.attach_journal(journal);
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    deposit(&mut engine, 1, amount("50"), 2).unwrap();
+    drop(engine);
+
+    // A flipped bit in the payload of the first entry
+    let mut content = std::fs::read(&path).unwrap();
+    content[10] ^= 1;
+    std::fs::write(&path, &content).unwrap();
+
+    let error = Journal::recover(&path, &mut TxEngine::default())
+        .err()
+        .unwrap();
+    assert!(matches!(error, JournalError::Corrupted { offset: 0 }));
+    // Nothing is truncated, the valid entry after the corrupted one is kept
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().len(),
+        content.len() as u64
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_journal_rejections_are_reported_by_offset() {
+    let path = std::env::temp_dir().join("transactions_test_journal_rejections.journal");
+    let _ = std::fs::remove_file(&path);
+
+    let mut engine = TxEngine::default();
+    let (journal, _) = Journal::recover(&path, &mut engine).unwrap();
+    engine.attach_journal(journal);
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+    let usd_offset = std::fs::metadata(&path).unwrap().len();
+    record_in(
+        &mut engine,
+        deposit_record(1, amount("50"), 2),
+        Currency::USD,
+    )
+    .unwrap();
+    drop(engine);
+
+    // The journal is replayed by an engine that no longer accepts dollars
+    let mut engine = TxEngine::new(EngineOptions {
+        currencies: Currencies {
+            accepted: vec![Currency::EUR],
+            default: Currency::EUR,
+        },
+        ..Default::default()
+    });
+    let (_, summary) = Journal::recover(&path, &mut engine).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(summary.replayed, 1);
+    assert_eq!(summary.rejected.len(), 1);
+    assert_eq!(summary.rejected[0].offset, usd_offset);
+    assert_eq!(
+        summary.rejected[0].error,
+        ProcessingError::UnknownCurrency(Currency::USD)
+    );
+}
+
+#[test]
+fn test_journal_failure_stops_the_engine() {
+    let path = std::env::temp_dir().join("transactions_test_journal_failure.journal");
+    let _ = std::fs::remove_file(&path);
+
+    let mut engine = TxEngine::default();
+    let (journal, _) = Journal::recover(&path, &mut engine).unwrap();
+    engine.attach_journal(journal);
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+
+    // The record is valid, but it's too large to be journaled, so it's not committed
+    let error = engine
+        .process_tx(TransactionRecord {
+            tx_type: TransactionRecordType::Limit {
+                limit: Some(positive(amount("50"))),
+                reason: "x".repeat(100_000),
+            },
+            client: 1,
+            tx: 0,
+            timestamp: None,
+            currency: None,
+        })
+        .unwrap_err();
+    assert!(matches!(error, ProcessingError::Journal(_)));
+    let client = engine.get_client(1).unwrap();
+    assert_eq!(engine.credit_limit(client, Currency::EUR), Amount::ZERO);
+    // The journal may hold a part of the entry, so nothing more is accepted
+    assert_eq!(deposit(&mut engine, 2, amount("10"), 2).unwrap_err(), error);
+    assert!(engine.get_client(2).is_none());
+    drop(engine);
+
+    let mut engine = TxEngine::default();
+    let (_, summary) = Journal::recover(&path, &mut engine).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(summary.replayed, 1);
+}
+
+#[test]
+fn test_journal_is_written_ahead_of_the_commit() {
+    let path = std::env::temp_dir().join("transactions_test_journal_write_ahead.journal");
+    let _ = std::fs::remove_file(&path);
+
+    let mut engine = TxEngine::default();
+    let (journal, _) = Journal::recover(&path, &mut engine).unwrap();
+    engine.attach_journal(journal);
+    deposit(&mut engine, 1, amount("100"), 1).unwrap();
+
+    // The full ledger check of the next commit fails on a posting that was never applied
+    engine.committed = super::ledger::LEDGER_CHECK_INTERVAL - 1;
+    engine.postings.append(Posting::new(
+        Account::Settlement,
+        Account::Chargebacks,
+        amount("5"),
+        Currency::EUR,
+    ));
+    let error = deposit(&mut engine, 1, amount("50"), 2).unwrap_err();
+    assert!(matches!(error, ProcessingError::Ledger(_)));
+    drop(engine);
+
+    // The record was journaled before its commit failed, so the replay applies it again
+    let mut engine = TxEngine::default();
+    let (_, summary) = Journal::recover(&path, &mut engine).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(summary.replayed, 2);
+    assert!(summary.rejected.is_empty());
+    let client = engine.get_balances(ClientOrder::Id).next().unwrap();
+    assert_eq!(client.total(), amount("150"));
+}
+
+#[test_case(2, EngineOptions::default(); "two shards")]
+#[test_case(3, EngineOptions::default(); "three shards")]
+#[test_case(8, EngineOptions::default(); "eight shards")]
+#[test_case(4, risk_rules_options(); "four shards with risk rules")]
+fn test_sharded_engine_matches_sequential(shards: usize, options: EngineOptions) {
+    let records = random_records(5000);
+
+    let mut sequential = TxEngine::new(options.clone());
+    let mut sequential_errors: Vec<_> = records
+        .iter()
+        .enumerate()
+        .filter_map(|(i, tx)| sequential.process_tx(tx.clone()).err().map(|e| (i, e)))
+        .collect();
+
+    let mut sharded = ShardedEngine::new(TxEngine::new(options), shards).unwrap();
+    records
+        .iter()
+        .enumerate()
+        .for_each(|(i, tx)| sharded.process_tx(tx.clone(), i));
+    let (merged, mut sharded_errors) = sharded.finish().unwrap();
+
+    sequential_errors.sort_by_key(|(i, _)| *i);
+    sharded_errors.sort_by_key(|(i, _)| *i);
+    assert_eq!(sharded_errors, sequential_errors);
+
+    let balances = |engine: &TxEngine| -> Vec<_> {
+        engine
+            .get_balances(ClientOrder::Id)
+            .map(|c| (c.id(), c.available(), c.held(), c.total(), c.is_locked()))
+            .collect()
+    };
+    assert_eq!(balances(&merged), balances(&sequential));
+    assert_eq!(merged.committed, sequential.committed);
+}
+
+#[test]
+fn test_sharded_merge_renumbers_sequences() {
+    let mut engine = TxEngine::default();
+    for tx in 1..=3 {
+        deposit(&mut engine, tx as ClientId, amount("10"), tx).unwrap();
+    }
+    let mut sequential = TxEngine::default();
+    for tx in 1..=3 {
+        deposit(&mut sequential, tx as ClientId, amount("10"), tx).unwrap();
+    }
+
+    let mut sharded = ShardedEngine::new(engine, 3).unwrap();
+    for tx in 4..=30 {
+        let record = deposit_record((tx % 5) as ClientId, amount("1"), tx);
+        sequential.process_tx(record.clone()).unwrap();
+        sharded.process_tx(record, ());
+    }
+    let (merged, errors) = sharded.finish().unwrap();
+    assert!(errors.is_empty());
+    assert_eq!(merged.committed, sequential.committed);
+
+    // Every sequence is used once, the transactions before the split keep theirs
+    let mut sequences: Vec<_> = merged
+        .committed_txs
+        .iter()
+        .map(|tx| tx.map(|tx| (tx.id(), tx.sequence())).unwrap())
+        .collect();
+    assert!(sequences.contains(&(1, 0)) && sequences.contains(&(3, 2)));
+    sequences.sort_unstable_by_key(|&(_, sequence)| sequence);
+    let expected: Vec<_> = (0..merged.committed).collect();
+    let sequences: Vec<_> = sequences.into_iter().map(|(_, s)| s).collect();
+    assert_eq!(sequences, expected);
+
+    // The transactions of each client are still in the commit order
+    for client in 0..5 {
+        let client_sequences: Vec<_> = merged
+            .client_transactions(client)
+            .map(|tx| tx.unwrap().sequence())
+            .collect();
+        assert!(client_sequences.is_sorted());
+    }
+}
+
+#[test]
+fn test_sharded_ledger_check_after_split() {
+    let mut engine = TxEngine::default();
+    for client in 1..=4 {
+        deposit(&mut engine, client, amount("100"), client.into()).unwrap();
+    }
+    withdrawal(&mut engine, 3, amount("40"), 5).unwrap();
+    // Every shard counts from the count before the split, so each crosses the interval
+    // of the full ledger check within the first records
+    let base = super::ledger::LEDGER_CHECK_INTERVAL - 5;
+    engine.committed = base;
+
+    let mut sharded = ShardedEngine::new(engine, 2).unwrap();
+    let records = 100;
+    for tx in 0..records {
+        let client = (tx % 4 + 1) as ClientId;
+        sharded.process_tx(deposit_record(client, amount("1"), tx + 6), ());
+    }
+    let (mut merged, errors) = sharded.finish().unwrap();
+
+    assert!(errors.is_empty());
+    assert_eq!(merged.committed, base + u64::from(records));
+    merged.check_ledger().unwrap();
+    let totals: Amount = merged
+        .get_balances(ClientOrder::Id)
+        .map(|balance| balance.total())
+        .fold(Amount::ZERO, |sum, total| sum.checked_add(total).unwrap());
+    assert_eq!(
+        totals,
+        Amount::from_units((360 + i64::from(records)) * 10_000)
+    );
+}
+
+#[test]
+fn test_sharded_ledger_overflow_fails_the_merge() {
+    // Each shard holds the largest amount, so their settlement accounts can't be merged
+    let mut sharded = ShardedEngine::new(TxEngine::default(), 2).unwrap();
+    for client in 1..=2 {
+        let largest = Amount::from_units(i64::MAX);
+        sharded.process_tx(deposit_record(client, largest, client.into()), ());
+    }
+
+    assert!(matches!(
+        sharded.finish().err().unwrap(),
+        ProcessingError::Ledger(_)
+    ));
 }
 
 mod utils {
-    use crate::{ClientId, Float, TransactionId};
+    use std::str::FromStr;
+
+    use crate::{
+        amount::{Amount, PositiveAmount},
+        ClientId, TransactionId,
+    };
 
     use super::*;
 
+    /// Generates records with ids colliding between clients, using a simple deterministic LCG
+    pub fn random_records(count: usize) -> Vec<TransactionRecord> {
+        let mut state: u64 = 42;
+        let mut next = |bound: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % bound
+        };
+
+        (0..count)
+            .map(|_| {
+                let client = next(20) as ClientId;
+                let tx = next(count as u64 / 3) as TransactionId;
+                let amount = positive(Amount::from_str(&(next(1000) + 1).to_string()).unwrap());
+                let tx_type = match next(10) {
+                    0..=3 => TransactionRecordType::Deposit { amount },
+                    4..=5 => TransactionRecordType::Withdrawal { amount },
+                    6..=7 => TransactionRecordType::Dispute,
+                    8 => TransactionRecordType::Resolve,
+                    _ => TransactionRecordType::Chargeback,
+                };
+                // Settlements without a currency, so they follow the referred transaction
+                let currency = tx_type
+                    .amount()
+                    .map(|_| [Currency::EUR, Currency::USD][next(2) as usize]);
+                TransactionRecord {
+                    tx_type,
+                    client,
+                    tx,
+                    timestamp: None,
+                    currency,
+                }
+            })
+            .collect()
+    }
+
+    /// Deposit of 10 by the client 1 at the given number of seconds since the epoch
+    pub fn deposit_at(
+        engine: &mut TxEngine,
+        tx: TransactionId,
+        secs: Option<i64>,
+    ) -> Result<(), ProcessingError> {
+        engine.process_tx(TransactionRecord {
+            tx_type: TransactionRecordType::Deposit {
+                amount: positive(amount("10")),
+            },
+            client: 1,
+            tx,
+            timestamp: secs.map(Timestamp::from_secs),
+            currency: None,
+        })
+    }
+
+    /// Dispute by the client 1 at the given number of seconds since the epoch
+    pub fn dispute_at(
+        engine: &mut TxEngine,
+        tx: TransactionId,
+        secs: Option<i64>,
+    ) -> Result<(), ProcessingError> {
+        engine.process_tx(TransactionRecord {
+            tx_type: TransactionRecordType::Dispute,
+            client: 1,
+            tx,
+            timestamp: secs.map(Timestamp::from_secs),
+            currency: None,
+        })
+    }
+
+    pub fn deposit_record(
+        client: ClientId,
+        amount: Amount,
+        tx: TransactionId,
+    ) -> TransactionRecord {
+        TransactionRecord {
+            tx_type: TransactionRecordType::Deposit {
+                amount: positive(amount),
+            },
+            client,
+            tx,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    pub fn withdrawal_record(
+        client: ClientId,
+        amount: Amount,
+        tx: TransactionId,
+    ) -> TransactionRecord {
+        TransactionRecord {
+            tx_type: TransactionRecordType::Withdrawal {
+                amount: positive(amount),
+            },
+            client,
+            tx,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    /// Processes the record with the currency set
+    pub fn record_in(
+        engine: &mut TxEngine,
+        record: TransactionRecord,
+        currency: Currency,
+    ) -> Result<(), ProcessingError> {
+        engine.process_tx(TransactionRecord {
+            currency: Some(currency),
+            ..record
+        })
+    }
+
+    /// Options with every risk rule set, strict enough to reject some random records
+    pub fn risk_rules_options() -> EngineOptions {
+        EngineOptions {
+            risk_rules: RiskRules {
+                max_withdrawal: [(Currency::EUR, amount("500"))].into(),
+                withdrawal_velocity: Some(WithdrawalVelocity {
+                    max_withdrawals: 2,
+                    records: 5.try_into().unwrap(),
+                }),
+                max_disputes: Some(3),
+                deny_list: [7].into(),
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn amount(value: &str) -> Amount {
+        value.parse().unwrap()
+    }
+
+    pub fn positive(amount: Amount) -> PositiveAmount {
+        amount.try_into().unwrap()
+    }
+
     pub fn deposit(
         engine: &mut TxEngine,
         client: ClientId,
-        amount: Float,
+        amount: Amount,
         tx: TransactionId,
     ) -> Result<(), ProcessingError> {
         engine.process_tx(TransactionRecord {
-            tx_type: TransactionRecordType::Deposit { amount },
+            tx_type: TransactionRecordType::Deposit {
+                amount: positive(amount),
+            },
             client,
             tx,
+            timestamp: None,
+            currency: None,
         })
     }
 
     pub fn withdrawal(
         engine: &mut TxEngine,
         client: ClientId,
-        amount: Float,
+        amount: Amount,
         tx: TransactionId,
     ) -> Result<(), ProcessingError> {
         engine.process_tx(TransactionRecord {
-            tx_type: TransactionRecordType::Withdrawal { amount },
+            tx_type: TransactionRecordType::Withdrawal {
+                amount: positive(amount),
+            },
             client,
             tx,
+            timestamp: None,
+            currency: None,
         })
     }
 
@@ -190,6 +1599,8 @@ mod utils {
             tx_type: TransactionRecordType::Dispute,
             client,
             tx,
+            timestamp: None,
+            currency: None,
         })
     }
 
@@ -202,6 +1613,8 @@ mod utils {
             tx_type: TransactionRecordType::Resolve,
             client,
             tx,
+            timestamp: None,
+            currency: None,
         })
     }
 
@@ -214,6 +1627,42 @@ mod utils {
             tx_type: TransactionRecordType::Chargeback,
             client,
             tx,
+            timestamp: None,
+            currency: None,
+        })
+    }
+
+    /// Limit record of the client in the default currency
+    pub fn limit(
+        engine: &mut TxEngine,
+        client: ClientId,
+        limit: Option<Amount>,
+    ) -> Result<(), ProcessingError> {
+        engine.process_tx(TransactionRecord {
+            tx_type: TransactionRecordType::Limit {
+                limit: limit.map(positive),
+                reason: "Overdraft line approved".to_string(),
+            },
+            client,
+            tx: 0,
+            timestamp: None,
+            currency: None,
+        })
+    }
+
+    pub fn unlock(
+        engine: &mut TxEngine,
+        client: ClientId,
+        tx: TransactionId,
+    ) -> Result<(), ProcessingError> {
+        engine.process_tx(TransactionRecord {
+            tx_type: TransactionRecordType::Unlock {
+                reason: "Chargeback settled with the client".to_string(),
+            },
+            client,
+            tx,
+            timestamp: None,
+            currency: None,
         })
     }
 }
@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    io,
+};
+
+use serde::{Deserialize, Serialize};
+
+use storage::StoredTx;
+pub use storage::{DiskStorage, MemoryStorage, TransactionStorage};
+
+use crate::{
+    amount::Amount,
+    currency::Currency,
+    errors::{ProcessingError, TransactionError},
+    timestamp::Timestamp,
+    ClientId, TransactionId,
+};
+
+mod storage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionState {
+    Committed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// Transaction type used for processing in the engine, contains additional information
+/// This type represents input transactions that includes the amount (withdrawal, deposit).
+/// Input transactions that refers to a previous transaction (dispute, resolve, chargeback)
+/// are reflected in a Transaction state.
+/// The kind of the transaction is recorded, as disputes of withdrawals may be handled
+/// differently, depending on the engine policy.
+/// The sequence number and the timestamp are used to limit the time a transaction
+/// can be disputed.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    id: TransactionId,
+    kind: TransactionKind,
+    amount: Amount,
+    currency: Currency,
+    client: ClientId,
+    state: TransactionState,
+    /// Number of deposits and withdrawals committed by the engine before this one
+    sequence: u64,
+    timestamp: Option<Timestamp>,
+}
+
+type TransactionResult<T> = Result<T, TransactionError>;
+
+// Custom implementation used to avoid exposing transaction details
+impl Debug for Transaction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transaction").finish()
+    }
+}
+
+impl Transaction {
+    pub fn new(
+        id: TransactionId,
+        kind: TransactionKind,
+        amount: Amount,
+        currency: Currency,
+        client: ClientId,
+        sequence: u64,
+        timestamp: Option<Timestamp>,
+    ) -> Self {
+        Self {
+            id,
+            kind,
+            amount,
+            currency,
+            client,
+            state: TransactionState::Committed,
+            sequence,
+            timestamp,
+        }
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Same transaction with another sequence number, used when shards are merged
+    pub fn resequenced(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    pub fn get_amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn kind(&self) -> TransactionKind {
+        self.kind
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.state
+    }
+
+    pub fn disputed(mut self) -> TransactionResult<Self> {
+        match self.state {
+            TransactionState::Committed => {
+                self.state = TransactionState::Disputed;
+                Ok(self)
+            }
+            _ => Err(TransactionError::CannotBeDisputed),
+        }
+    }
+
+    pub fn resolved(mut self) -> TransactionResult<Self> {
+        match self.state {
+            TransactionState::Disputed => {
+                self.state = TransactionState::Resolved;
+                Ok(self)
+            }
+            _ => Err(TransactionError::NotUnderDispute),
+        }
+    }
+
+    pub fn charged_back(mut self) -> TransactionResult<Self> {
+        match self.state {
+            TransactionState::Disputed => {
+                self.state = TransactionState::ChargedBack;
+                Ok(self)
+            }
+            _ => Err(TransactionError::NotUnderDispute),
+        }
+    }
+
+    pub fn id(&self) -> TransactionId {
+        self.id
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        self.client
+    }
+}
+
+/// First and last transaction of a client, the transactions in between are linked
+/// by the storage entries
+#[derive(Clone, Copy)]
+struct ClientTxs {
+    first: TransactionId,
+    last: TransactionId,
+}
+
+/// Committed transactions by their id, with an index of the transactions of each client
+/// in the order they were committed. The transactions are kept by a storage, which
+/// may hold them in memory or on disk, the store itself keeps only the ends of the index.
+pub struct TransactionStore {
+    storage: Box<dyn TransactionStorage>,
+    by_client: HashMap<ClientId, ClientTxs>,
+}
+
+impl Default for TransactionStore {
+    fn default() -> Self {
+        Self::new(Box::<MemoryStorage>::default())
+    }
+}
+
+impl TransactionStore {
+    pub fn new(storage: Box<dyn TransactionStorage>) -> Self {
+        Self {
+            storage,
+            by_client: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, id: &TransactionId) -> Result<Transaction, ProcessingError> {
+        self.storage
+            .get(*id)
+            .map_err(storage_error)?
+            .map(|stored| stored.tx)
+            .ok_or(TransactionError::ReferredTxNotFound.into())
+    }
+
+    /// Transactions of the client in the order they were committed
+    pub fn client_transactions(
+        &self,
+        client: ClientId,
+    ) -> impl Iterator<Item = Result<Transaction, ProcessingError>> + '_ {
+        self.chain(self.by_client.get(&client).map(|txs| txs.first))
+    }
+
+    /// Transactions of each client are returned in the order they were committed,
+    /// so the index is rebuilt the same when they are inserted into a new store
+    pub fn iter(&self) -> impl Iterator<Item = Result<Transaction, ProcessingError>> + '_ {
+        self.by_client
+            .values()
+            .flat_map(|txs| self.chain(Some(txs.first)))
+    }
+
+    /// Follows the links between the transactions of a client
+    fn chain(
+        &self,
+        first: Option<TransactionId>,
+    ) -> impl Iterator<Item = Result<Transaction, ProcessingError>> + '_ {
+        let mut next = first;
+        std::iter::from_fn(move || {
+            let id = next.take()?;
+            Some(self.indexed(id).map(|stored| {
+                next = stored.next;
+                stored.tx
+            }))
+        })
+    }
+
+    /// Entry of a transaction referred to by the index
+    fn indexed(&self, id: TransactionId) -> Result<StoredTx, ProcessingError> {
+        self.storage
+            .get(id)
+            .and_then(|stored| {
+                stored
+                    .ok_or_else(|| io::Error::other(format!("Indexed transaction {id} is missing")))
+            })
+            .map_err(storage_error)
+    }
+
+    /// Removes the expired transactions which are not under dispute. The transactions
+    /// of each client are visited from the oldest one up to the first one that is not expired,
+    /// so the cost depends on the number of the expired transactions only.
+    /// Ids of the removed transactions are no longer reported as used.
+    pub fn evict_expired(
+        &mut self,
+        is_expired: impl Fn(&Transaction) -> bool,
+    ) -> Result<usize, ProcessingError> {
+        let mut evicted = 0;
+        let clients: Vec<_> = self.by_client.keys().copied().collect();
+        for client in clients {
+            let txs = self.by_client[&client];
+            let mut expired = Vec::new();
+            let mut rest = Some(txs.first);
+            while let Some(id) = rest {
+                let stored = self.indexed(id)?;
+                if !is_expired(&stored.tx) {
+                    break;
+                }
+                rest = stored.next;
+                expired.push(stored.tx);
+            }
+
+            let (kept, removed): (Vec<_>, Vec<_>) = expired
+                .into_iter()
+                .partition(|tx| tx.state == TransactionState::Disputed);
+            if removed.is_empty() {
+                continue;
+            }
+            for tx in &removed {
+                self.storage.remove(tx.id).map_err(storage_error)?;
+            }
+            evicted += removed.len();
+
+            // The kept transactions are linked to each other and to the rest of the chain
+            let mut next = rest;
+            for tx in kept.iter().rev() {
+                self.storage
+                    .put(StoredTx {
+                        tx: tx.clone(),
+                        next,
+                    })
+                    .map_err(storage_error)?;
+                next = Some(tx.id);
+            }
+            match next {
+                Some(first) => {
+                    let last = if rest.is_some() {
+                        txs.last
+                    } else {
+                        kept[kept.len() - 1].id
+                    };
+                    self.by_client.insert(client, ClientTxs { first, last });
+                }
+                None => {
+                    self.by_client.remove(&client);
+                }
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Fails if the id is already used by a committed transaction
+    pub fn check_unused(&self, id: &TransactionId) -> Result<(), ProcessingError> {
+        if self.storage.get(*id).map_err(storage_error)?.is_some() {
+            return Err(TransactionError::DuplicateTransactionId.into());
+        }
+        Ok(())
+    }
+
+    /// Inserts a new transaction, reuse of an existing id is rejected
+    pub fn insert(&mut self, tx: Transaction) -> Result<(), ProcessingError> {
+        self.check_unused(&tx.id)?;
+        let (id, client) = (tx.id, tx.client);
+        self.storage
+            .put(StoredTx { tx, next: None })
+            .map_err(storage_error)?;
+
+        let first = match self.by_client.get(&client) {
+            Some(txs) => {
+                let mut last = self.indexed(txs.last)?;
+                last.next = Some(id);
+                self.storage.put(last).map_err(storage_error)?;
+                txs.first
+            }
+            None => id,
+        };
+        self.by_client.insert(client, ClientTxs { first, last: id });
+        Ok(())
+    }
+
+    /// Replaces an already committed transaction, e.g. with its new dispute state
+    pub fn update(&mut self, tx: Transaction) -> Result<(), ProcessingError> {
+        let stored = self.storage.get(tx.id).map_err(storage_error)?;
+        let stored = stored.expect("Updated transaction is stored");
+        debug_assert_eq!(stored.tx.client, tx.client);
+        self.storage
+            .put(StoredTx {
+                tx,
+                next: stored.next,
+            })
+            .map_err(storage_error)
+    }
+}
+
+fn storage_error(e: io::Error) -> ProcessingError {
+    ProcessingError::Storage(e.to_string())
+}
@@ -0,0 +1,297 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+use super::{Transaction, TransactionKind, TransactionState};
+use crate::{amount::Amount, currency::Currency, timestamp::Timestamp, ClientId, TransactionId};
+
+/// Transaction as kept by a storage, linked to the next transaction of the same client
+#[derive(Debug, Clone)]
+pub struct StoredTx {
+    pub tx: Transaction,
+    pub next: Option<TransactionId>,
+}
+
+/// Storage of committed transactions by their id. The `TransactionStore` keeps the rules
+/// (unique ids, the per-client order) on top of it, a storage only reads and writes entries.
+pub trait TransactionStorage: Send {
+    fn get(&self, id: TransactionId) -> io::Result<Option<StoredTx>>;
+
+    /// Inserts or replaces the entry
+    fn put(&mut self, tx: StoredTx) -> io::Result<()>;
+
+    fn remove(&mut self, id: TransactionId) -> io::Result<()>;
+}
+
+/// Keeps all transactions in memory
+#[derive(Default)]
+pub struct MemoryStorage {
+    store: HashMap<TransactionId, StoredTx>,
+}
+
+impl TransactionStorage for MemoryStorage {
+    fn get(&self, id: TransactionId) -> io::Result<Option<StoredTx>> {
+        Ok(self.store.get(&id).cloned())
+    }
+
+    fn put(&mut self, tx: StoredTx) -> io::Result<()> {
+        self.store.insert(tx.tx.id, tx);
+        Ok(())
+    }
+
+    fn remove(&mut self, id: TransactionId) -> io::Result<()> {
+        self.store.remove(&id);
+        Ok(())
+    }
+}
+
+/// Size of an entry in the storage file:
+/// `[u8 tag][u8 state][u16 client][u32 next][i64 amount units][u64 sequence][i64 timestamp]
+/// [3 bytes currency code][5 bytes padding]`, little endian. The tag holds the kind, with the highest bit set if the entry links
+/// to a next transaction and the next one if the transaction has a timestamp.
+/// A zeroed entry is empty.
+const ENTRY_SIZE: u64 = 40;
+const HAS_NEXT: u8 = 0x80;
+const HAS_TIMESTAMP: u8 = 0x40;
+const FLAGS: u8 = HAS_NEXT | HAS_TIMESTAMP;
+
+/// Keeps the transactions in a file, with at most `capacity` most recently used ones
+/// in memory - a read counts as a use as well as a write.
+/// The entry of a transaction is placed at an offset given by its id, so no index is needed.
+/// The file is sparse - only the pages holding used ids take disk space. Every write goes
+/// straight to the file, so evicting an entry from memory never needs a write.
+///
+/// The file holds the state of a single run only, it is truncated when opened
+/// and removed when the storage is dropped.
+pub struct DiskStorage {
+    file: File,
+    path: PathBuf,
+    cache: RefCell<Cache>,
+}
+
+struct Cache {
+    /// Cached entries with the time of their last use
+    entries: HashMap<TransactionId, (StoredTx, u64)>,
+    /// Cached ids by the time of their last use, the least recently used one is evicted first
+    order: BTreeMap<u64, TransactionId>,
+    /// Number of the uses so far, the time of the last one
+    uses: u64,
+    capacity: usize,
+}
+
+impl Cache {
+    /// Cached entry, which becomes the most recently used one
+    fn get(&mut self, id: TransactionId) -> Option<StoredTx> {
+        let (tx, used) = self.entries.get_mut(&id)?;
+        self.order.remove(used);
+        self.uses += 1;
+        *used = self.uses;
+        self.order.insert(self.uses, id);
+        Some(tx.clone())
+    }
+
+    fn insert(&mut self, tx: StoredTx) {
+        let id = tx.tx.id;
+        self.uses += 1;
+        if let Some((_, used)) = self.entries.insert(id, (tx, self.uses)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.uses, id);
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, id: TransactionId) {
+        if let Some((_, used)) = self.entries.remove(&id) {
+            self.order.remove(&used);
+        }
+    }
+}
+
+impl DiskStorage {
+    pub fn create(path: &Path, capacity: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            cache: RefCell::new(Cache {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                uses: 0,
+                capacity: capacity.max(1),
+            }),
+        })
+    }
+
+    fn read_entry(&self, id: TransactionId) -> io::Result<Option<StoredTx>> {
+        let mut entry = [0; ENTRY_SIZE as usize];
+        match self
+            .file
+            .read_exact_at(&mut entry, u64::from(id) * ENTRY_SIZE)
+        {
+            Ok(()) => decode(id, &entry),
+            // Ids after the last written entry
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl TransactionStorage for DiskStorage {
+    fn get(&self, id: TransactionId) -> io::Result<Option<StoredTx>> {
+        if let Some(tx) = self.cache.borrow_mut().get(id) {
+            return Ok(Some(tx));
+        }
+        let tx = self.read_entry(id)?;
+        if let Some(tx) = &tx {
+            self.cache.borrow_mut().insert(tx.clone());
+        }
+        Ok(tx)
+    }
+
+    fn put(&mut self, tx: StoredTx) -> io::Result<()> {
+        let offset = u64::from(tx.tx.id) * ENTRY_SIZE;
+        self.file.write_all_at(&encode(&tx), offset)?;
+        self.cache.get_mut().insert(tx);
+        Ok(())
+    }
+
+    /// The entry is zeroed and dropped from the cache
+    fn remove(&mut self, id: TransactionId) -> io::Result<()> {
+        let offset = u64::from(id) * ENTRY_SIZE;
+        self.file.write_all_at(&[0; ENTRY_SIZE as usize], offset)?;
+        self.cache.get_mut().remove(id);
+        Ok(())
+    }
+}
+
+impl Drop for DiskStorage {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn encode(stored: &StoredTx) -> [u8; ENTRY_SIZE as usize] {
+    let tx = &stored.tx;
+    let mut tag = match tx.kind {
+        TransactionKind::Deposit => 1,
+        TransactionKind::Withdrawal => 2,
+    };
+    if stored.next.is_some() {
+        tag |= HAS_NEXT;
+    }
+    if tx.timestamp.is_some() {
+        tag |= HAS_TIMESTAMP;
+    }
+    let state = match tx.state {
+        TransactionState::Committed => 0,
+        TransactionState::Disputed => 1,
+        TransactionState::Resolved => 2,
+        TransactionState::ChargedBack => 3,
+    };
+
+    let mut entry = [0; ENTRY_SIZE as usize];
+    entry[0] = tag;
+    entry[1] = state;
+    entry[2..4].copy_from_slice(&tx.client.to_le_bytes());
+    entry[4..8].copy_from_slice(&stored.next.unwrap_or_default().to_le_bytes());
+    entry[8..16].copy_from_slice(&tx.amount.units().to_le_bytes());
+    entry[16..24].copy_from_slice(&tx.sequence.to_le_bytes());
+    entry[24..32].copy_from_slice(&tx.timestamp.map_or(0, Timestamp::secs).to_le_bytes());
+    entry[32..35].copy_from_slice(&tx.currency.as_bytes());
+    entry
+}
+
+fn decode(id: TransactionId, entry: &[u8; ENTRY_SIZE as usize]) -> io::Result<Option<StoredTx>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid transaction entry");
+    let kind = match entry[0] & !FLAGS {
+        0 => return Ok(None),
+        1 => TransactionKind::Deposit,
+        2 => TransactionKind::Withdrawal,
+        _ => return Err(invalid()),
+    };
+    let state = match entry[1] {
+        0 => TransactionState::Committed,
+        1 => TransactionState::Disputed,
+        2 => TransactionState::Resolved,
+        3 => TransactionState::ChargedBack,
+        _ => return Err(invalid()),
+    };
+    let field = |range: std::ops::Range<usize>| &entry[range];
+    let client = ClientId::from_le_bytes(field(2..4).try_into().expect("Entry size is fixed"));
+    let next = TransactionId::from_le_bytes(field(4..8).try_into().expect("Entry size is fixed"));
+    let units = i64::from_le_bytes(field(8..16).try_into().expect("Entry size is fixed"));
+    let sequence = u64::from_le_bytes(field(16..24).try_into().expect("Entry size is fixed"));
+    let timestamp = i64::from_le_bytes(field(24..32).try_into().expect("Entry size is fixed"));
+    let currency = Currency::from_bytes(field(32..35).try_into().expect("Entry size is fixed"))
+        .ok_or_else(invalid)?;
+
+    Ok(Some(StoredTx {
+        tx: Transaction {
+            id,
+            kind,
+            amount: Amount::from_units(units),
+            currency,
+            client,
+            state,
+            sequence,
+            timestamp: (entry[0] & HAS_TIMESTAMP != 0).then(|| Timestamp::from_secs(timestamp)),
+        },
+        next: (entry[0] & HAS_NEXT != 0).then_some(next),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(id: TransactionId) -> StoredTx {
+        let amount = Amount::from_units(10_000);
+        let tx = Transaction::new(
+            id,
+            TransactionKind::Deposit,
+            amount,
+            Currency::EUR,
+            1,
+            0,
+            None,
+        );
+        StoredTx { tx, next: None }
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let path = std::env::temp_dir().join("transactions_test_storage_cache.bin");
+        let mut storage = DiskStorage::create(&path, 2).unwrap();
+        storage.put(stored(1)).unwrap();
+        storage.put(stored(2)).unwrap();
+        // The read makes the first transaction more recently used than the second one
+        storage.get(1).unwrap().unwrap();
+        storage.put(stored(3)).unwrap();
+
+        // Only the cached transactions are left once the file is emptied
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        assert!(storage.get(1).unwrap().is_some());
+        assert!(storage.get(2).unwrap().is_none());
+        assert!(storage.get(3).unwrap().is_some());
+    }
+}
@@ -1,17 +1,70 @@
-#[derive(Debug, thiserror::Error)]
+use crate::{
+    amount::Amount, conversion::Rate, currency::Currency, engine::RiskRule, timestamp::Timestamp,
+    ClientId,
+};
+
+#[derive(Debug, Clone, thiserror::Error)]
 #[cfg_attr(test, derive(PartialEq))]
 pub enum ProcessingError {
     #[error("Insufficient funds")]
     InsufficientFunds,
+    #[error("Withdrawal exceeds the credit limit of {0}")]
+    CreditLimitExceeded(Amount),
     #[error("Client is locked")]
     ClientLocked,
+    #[error("Client is not locked")]
+    ClientNotLocked,
     #[error("Client ID does not match")]
     ClientIdNotMatched,
+    #[error("Amount overflow")]
+    AmountOverflow,
+    #[error("Currency {0} is not accepted")]
+    UnknownCurrency(Currency),
+    #[error("Violates the {rule} rule: {detail}")]
+    RuleViolation { rule: RiskRule, detail: String },
+    #[error("Record is older than the latest accepted record at {latest}")]
+    OutOfOrder { latest: Timestamp },
     #[error(transparent)]
     InvalidTransaction(#[from] TransactionError),
+    #[error("Failed to write the journal: {0}")]
+    Journal(String),
+    #[error("Failed to access the transaction storage: {0}")]
+    Storage(String),
+    #[error("Ledger invariant violated: {0}")]
+    Ledger(String),
 }
 
-#[derive(Debug, thiserror::Error)]
+impl ProcessingError {
+    /// Failures of the engine itself rather than of the record, the processing
+    /// can't continue after them
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ProcessingError::Journal(_) | ProcessingError::Storage(_) | ProcessingError::Ledger(_)
+        )
+    }
+
+    /// Machine-readable code of the error, used in the rejection report
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcessingError::InsufficientFunds => "insufficient_funds",
+            ProcessingError::CreditLimitExceeded(_) => "credit_limit_exceeded",
+            ProcessingError::ClientLocked => "client_locked",
+            ProcessingError::ClientNotLocked => "client_not_locked",
+            ProcessingError::ClientIdNotMatched => "client_id_not_matched",
+            ProcessingError::AmountOverflow => "amount_overflow",
+            ProcessingError::UnknownCurrency(_) => "unknown_currency",
+            ProcessingError::RuleViolation { rule, .. } => rule.code(),
+            ProcessingError::OutOfOrder { .. } => "out_of_order",
+            ProcessingError::InvalidTransaction(e) => e.code(),
+            ProcessingError::Journal(_) => "journal_write_failed",
+            ProcessingError::Storage(_) => "storage_failed",
+            ProcessingError::Ledger(_) => "ledger_invariant_violated",
+        }
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
 #[cfg_attr(test, derive(PartialEq))]
 pub enum TransactionError {
     #[error("Referred transaction not found")]
@@ -20,4 +73,131 @@ pub enum TransactionError {
     CannotBeDisputed,
     #[error("Referred transaction is not under dispute")]
     NotUnderDispute,
+    #[error("Transaction ID is already used")]
+    DuplicateTransactionId,
+    #[error("Referred transaction is a withdrawal and cannot be disputed")]
+    WithdrawalNotDisputable,
+    #[error("Referred transaction is out of the dispute window")]
+    DisputeWindowExpired,
+    #[error("Referred transaction is in another currency")]
+    CurrencyMismatch,
+}
+
+impl TransactionError {
+    /// Machine-readable code of the error, used in the rejection report
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::ReferredTxNotFound => "referred_tx_not_found",
+            TransactionError::CannotBeDisputed => "cannot_be_disputed",
+            TransactionError::NotUnderDispute => "not_under_dispute",
+            TransactionError::DuplicateTransactionId => "duplicate_transaction_id",
+            TransactionError::WithdrawalNotDisputable => "withdrawal_not_disputable",
+            TransactionError::DisputeWindowExpired => "dispute_window_expired",
+            TransactionError::CurrencyMismatch => "currency_mismatch",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum AmountError {
+    #[error("Invalid amount: {0:?}")]
+    Invalid(String),
+    #[error("Amount {0:?} has more than 4 decimal places")]
+    TooPrecise(String),
+    #[error("Amount {0:?} is out of range")]
+    OutOfRange(String),
+    #[error("Amount {0:?} is negative")]
+    Negative(String),
+    #[error("Amount {0:?} is zero")]
+    Zero(String),
+    #[error("Amount {0:?} is not a number")]
+    NotANumber(String),
+    #[error("Amount {0:?} is infinite")]
+    Infinite(String),
+}
+
+/// Invalid combination of the fields of an input record, shared by all input formats
+#[derive(Debug, thiserror::Error)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum RecordError {
+    #[error("Unknown transaction type {0:?}")]
+    UnknownType(String),
+    #[error("Missing amount of a {0}")]
+    MissingAmount(&'static str),
+    #[error("Missing reason of a {0}")]
+    MissingReason(&'static str),
+}
+
+/// Violation of the ledger invariants, which means a bug in the processing
+#[derive(Debug, thiserror::Error)]
+pub enum LedgerError {
+    #[error("Ledger is out of balance by {0}")]
+    Imbalance(String),
+    #[error("Client {0} holds a negative amount")]
+    NegativeHeld(ClientId),
+    #[error("Ledger balance in {0} is out of range")]
+    Overflow(Currency),
+    #[error("Balance of the {0} doesn't match the posting log")]
+    LogMismatch(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum TimestampError {
+    #[error("Invalid timestamp {0:?}, expected RFC 3339 or seconds since the Unix epoch")]
+    Invalid(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum CurrencyError {
+    #[error("Invalid currency {0:?}, expected a three-letter ISO 4217 code")]
+    Invalid(String),
+}
+
+/// Failure of the conversion of the balances into the base currency
+#[derive(Debug, thiserror::Error)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum ConversionError {
+    #[error("Invalid rate {0:?}, expected a positive number with up to 8 decimal places")]
+    InvalidRate(String),
+    #[error("No rate of {0} is given")]
+    MissingRate(Currency),
+    #[error("Rate of {0} is given more than once")]
+    DuplicateRate(Currency),
+    #[error("Base currency {0} can only have the rate of 1, not {1}")]
+    BaseRate(Currency, Rate),
+    #[error("Converted amount is out of range")]
+    Overflow,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Failed to access snapshot: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid snapshot format: {0}")]
+    Format(#[from] serde_json::Error),
+    #[error("Unsupported snapshot version {found}, expected version {expected}")]
+    VersionMismatch { expected: u32, found: u32 },
+    #[error("Corrupted snapshot: {0}")]
+    Corrupted(&'static str),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("Failed to access journal: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid journal record at byte {offset}: {source}")]
+    InvalidRecord {
+        offset: u64,
+        source: serde_json::Error,
+    },
+    #[error("Corrupted journal entry at byte {offset}, followed by more entries")]
+    Corrupted { offset: u64 },
+    #[error("Failed to replay journal record at byte {offset}: {source}")]
+    Replay {
+        offset: u64,
+        source: ProcessingError,
+    },
 }
@@ -0,0 +1,287 @@
+use std::{
+    io::Read,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::{
+    engine::TxEngine,
+    errors::{ProcessingError, TransactionError},
+    rejections::PARSE_ERROR_CODE,
+    transaction_record::TransactionRecord,
+    ClientId, TransactionId,
+};
+
+/// JSON API over a long-lived engine:
+/// - `POST /transactions` - processes a record given as a JSON object, returns the client balances
+/// - `GET /clients` - balances of all clients, ordered by the client id
+/// - `GET /clients/{id}` - balances of a single client
+/// - `GET /clients/{id}/transactions` - deposits and withdrawals of the client, oldest first
+/// - `GET /transactions/{id}` - a deposit or withdrawal with its dispute state
+///
+/// Rejected records are answered with a status code derived from the processing error
+/// and a `{"code": ..., "message": ...}` body, the codes are the same as in the rejection report.
+/// Requests are handled by a fixed number of worker threads, the rest wait for a free one.
+pub struct HttpServer {
+    server: Arc<tiny_http::Server>,
+    engine: Arc<Mutex<TxEngine>>,
+    workers: u16,
+}
+
+/// Largest accepted request body, larger ones are answered with 413
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Status code and JSON body of a response
+struct Reply {
+    status: u16,
+    body: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+}
+
+impl Reply {
+    fn ok(status: u16, body: &impl Serialize) -> Self {
+        match serde_json::to_string(body) {
+            Ok(body) => Self { status, body },
+            Err(e) => Self::internal_error(e),
+        }
+    }
+
+    fn error(status: u16, code: &str, message: impl ToString) -> Self {
+        let body = ErrorBody {
+            code,
+            message: message.to_string(),
+        };
+        Self {
+            status,
+            body: serde_json::to_string(&body).expect("Error body is always serializable"),
+        }
+    }
+
+    fn not_found(message: impl ToString) -> Self {
+        Self::error(404, "not_found", message)
+    }
+
+    fn internal_error(message: impl ToString) -> Self {
+        Self::error(500, "internal_error", message)
+    }
+}
+
+impl From<ProcessingError> for Reply {
+    fn from(error: ProcessingError) -> Self {
+        Self::error(status_code(&error), error.code(), &error)
+    }
+}
+
+impl HttpServer {
+    pub fn bind(addr: SocketAddr, engine: TxEngine, workers: u16) -> anyhow::Result<Self> {
+        let server = tiny_http::Server::http(addr)
+            .map_err(|e| anyhow::anyhow!("Failed to bind {addr}: {e}"))?;
+        Ok(Self {
+            server: Arc::new(server),
+            engine: Arc::new(Mutex::new(engine)),
+            workers: workers.max(1),
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    pub fn run(self) -> anyhow::Result<()> {
+        if let Some(addr) = self.local_addr() {
+            eprintln!("Listening on http://{addr}");
+        }
+        let workers: Vec<_> = (0..self.workers)
+            .map(|_| {
+                let server = self.server.clone();
+                let engine = self.engine.clone();
+                thread::spawn(move || {
+                    for request in server.incoming_requests() {
+                        handle_request(request, &engine);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            if worker.join().is_err() {
+                anyhow::bail!("HTTP worker panicked");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn handle_request(mut request: Request, engine: &Mutex<TxEngine>) {
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let method = request.method().clone();
+
+    let reply = match (&method, segments.as_slice()) {
+        (Method::Post, ["transactions"]) => {
+            let length = request.body_length();
+            post_transaction(request.as_reader(), length, engine)
+        }
+        (Method::Get, ["clients"]) => with_engine(engine, |engine| {
+            let clients: Vec<_> = engine.get_clients().collect();
+            Reply::ok(200, &clients)
+        }),
+        (Method::Get, ["clients", id]) => match id.parse::<ClientId>() {
+            Ok(id) => with_engine(engine, |engine| match engine.get_client(id) {
+                Some(client) => Reply::ok(200, client),
+                None => Reply::not_found(format!("Client {id} not found")),
+            }),
+            Err(e) => Reply::error(400, PARSE_ERROR_CODE, format!("Invalid client id: {e}")),
+        },
+        (Method::Get, ["clients", id, "transactions"]) => match id.parse::<ClientId>() {
+            Ok(id) => with_engine(engine, |engine| {
+                match engine
+                    .client_transactions(id)
+                    .collect::<Result<Vec<_>, _>>()
+                {
+                    Ok(transactions) => Reply::ok(200, &transactions),
+                    Err(e) => e.into(),
+                }
+            }),
+            Err(e) => Reply::error(400, PARSE_ERROR_CODE, format!("Invalid client id: {e}")),
+        },
+        (Method::Get, ["transactions", id]) => match id.parse::<TransactionId>() {
+            Ok(id) => with_engine(engine, |engine| match engine.get_transaction(id) {
+                Ok(Some(tx)) => Reply::ok(200, &tx),
+                Ok(None) => Reply::not_found(format!("Transaction {id} not found")),
+                Err(e) => e.into(),
+            }),
+            Err(e) => Reply::error(
+                400,
+                PARSE_ERROR_CODE,
+                format!("Invalid transaction id: {e}"),
+            ),
+        },
+        (
+            _,
+            ["transactions"]
+            | ["transactions", _]
+            | ["clients"]
+            | ["clients", _]
+            | ["clients", _, "transactions"],
+        ) => Reply::error(
+            405,
+            "method_not_allowed",
+            format!("{method} is not allowed"),
+        ),
+        _ => Reply::not_found(format!("No resource at {path}")),
+    };
+
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("Header is always valid");
+    let response = Response::from_string(reply.body)
+        .with_status_code(reply.status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        eprintln!("Failed to respond to {method} {path}: {e}");
+    }
+}
+
+fn post_transaction(body: &mut dyn Read, length: Option<usize>, engine: &Mutex<TxEngine>) -> Reply {
+    let too_large = || {
+        Reply::error(
+            413,
+            "payload_too_large",
+            format!("Request body is larger than {MAX_BODY_SIZE} bytes"),
+        )
+    };
+    if length.is_some_and(|length| length > MAX_BODY_SIZE) {
+        return too_large();
+    }
+    // The length may be unknown for chunked bodies, so the read is capped as well
+    let mut buf = Vec::new();
+    if let Err(e) = body.take(MAX_BODY_SIZE as u64 + 1).read_to_end(&mut buf) {
+        return Reply::error(400, PARSE_ERROR_CODE, e);
+    }
+    if buf.len() > MAX_BODY_SIZE {
+        return too_large();
+    }
+    let record = match TransactionRecord::from_json(&buf) {
+        Ok(record) => record,
+        Err(e) => return Reply::error(400, PARSE_ERROR_CODE, e),
+    };
+    let client = record.client;
+
+    with_engine(engine, |engine| match engine.process_tx(record) {
+        Ok(()) => match engine.get_client(client) {
+            Some(client) => Reply::ok(200, client),
+            None => Reply::not_found(format!("Client {client} not found")),
+        },
+        Err(e) => e.into(),
+    })
+}
+
+/// Runs the handler with the locked engine. A worker that panicked while holding the lock
+/// may have left the engine half-updated, so it's not used anymore and every request
+/// is answered with 500.
+fn with_engine(engine: &Mutex<TxEngine>, handler: impl FnOnce(&mut TxEngine) -> Reply) -> Reply {
+    match engine.lock() {
+        Ok(mut engine) => handler(&mut engine),
+        Err(_) => Reply::internal_error("Engine is unavailable after a failure of another request"),
+    }
+}
+
+fn status_code(error: &ProcessingError) -> u16 {
+    match error {
+        ProcessingError::InsufficientFunds
+        | ProcessingError::CreditLimitExceeded(_)
+        | ProcessingError::ClientIdNotMatched
+        | ProcessingError::AmountOverflow
+        | ProcessingError::UnknownCurrency(_)
+        | ProcessingError::RuleViolation { .. } => 422,
+        ProcessingError::ClientLocked => 423,
+        ProcessingError::ClientNotLocked | ProcessingError::OutOfOrder { .. } => 409,
+        ProcessingError::Journal(_) | ProcessingError::Storage(_) | ProcessingError::Ledger(_) => {
+            500
+        }
+        ProcessingError::InvalidTransaction(e) => match e {
+            TransactionError::ReferredTxNotFound => 404,
+            TransactionError::CannotBeDisputed
+            | TransactionError::NotUnderDispute
+            | TransactionError::DuplicateTransactionId
+            | TransactionError::WithdrawalNotDisputable
+            | TransactionError::DisputeWindowExpired => 409,
+            TransactionError::CurrencyMismatch => 422,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poisoned_engine_is_an_internal_error() {
+        let engine = Mutex::new(TxEngine::default());
+        thread::scope(|scope| {
+            let holder = scope.spawn(|| {
+                let _engine = engine.lock().unwrap();
+                panic!("Request failed while holding the engine");
+            });
+            assert!(holder.join().is_err());
+        });
+
+        let record = br#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1"}"#;
+        let reply = post_transaction(&mut &record[..], Some(record.len()), &engine);
+        assert_eq!(reply.status, 500);
+        assert!(reply.body.contains("internal_error"));
+    }
+}
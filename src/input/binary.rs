@@ -0,0 +1,287 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+use super::{InputRecord, ParseFailure, RawRecord};
+use crate::{
+    amount::{Amount, PositiveAmount},
+    currency::Currency,
+    timestamp::Timestamp,
+    transaction_record::{RecordFields, TransactionRecord},
+    ClientId, TransactionId,
+};
+
+const HAS_AMOUNT: u8 = 1;
+const HAS_TIMESTAMP: u8 = 1 << 1;
+const HAS_CURRENCY: u8 = 1 << 2;
+const HAS_REASON: u8 = 1 << 3;
+const KNOWN_FLAGS: u8 = HAS_AMOUNT | HAS_TIMESTAMP | HAS_CURRENCY | HAS_REASON;
+
+/// Records of a binary input. Each record is prefixed by the length of its payload,
+/// all integers are little endian:
+///
+/// | field  | size | content                                                  |
+/// |--------|------|----------------------------------------------------------|
+/// | length | 2    | length of the rest of the record                         |
+/// | flags  | 1    | optional fields present in the record, see below         |
+/// | type   | 1+n  | length of the type name and the name (case-insensitive)  |
+/// | client | 2    | client id                                                |
+/// | tx     | 4    | transaction id                                           |
+/// | amount | 8    | signed number of ten-thousandths, with `HAS_AMOUNT`      |
+/// | time   | 8    | signed number of seconds since the Unix epoch, with `HAS_TIMESTAMP` |
+/// | currency | 3  | ASCII currency code, with `HAS_CURRENCY`                 |
+/// | reason | n    | UTF-8 text filling the rest of the record, with `HAS_REASON` |
+///
+/// Each optional field is present only when its flag is set, records with unknown flags
+/// are rejected. A limit without an amount revokes the credit line. The type name is kept
+/// as text, so the records go through the same validation as the textual formats.
+pub struct BinaryRecords {
+    reader: BufReader<Box<dyn Read>>,
+    record: u64,
+    torn: bool,
+}
+
+impl BinaryRecords {
+    pub fn new(reader: Box<dyn Read>) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            record: 0,
+            torn: false,
+        }
+    }
+
+    /// Reads the payload of the next record, `None` at the end of the input
+    /// or for a record cut off by the end of the input
+    fn read_payload(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let mut len = [0; 2];
+        let mut payload = Vec::new();
+        let read = self.reader.read_exact(&mut len).and_then(|()| {
+            payload.resize(u16::from_le_bytes(len) as usize, 0);
+            self.reader.read_exact(&mut payload)
+        });
+        match read {
+            Ok(()) => Ok(Some(payload)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.torn = true;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Iterator for BinaryRecords {
+    type Item = io::Result<InputRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.torn {
+            return None;
+        }
+        let payload = match self.read_payload() {
+            Ok(payload) => payload,
+            Err(e) => return Some(Err(e)),
+        };
+        self.record += 1;
+
+        let (raw, parsed) = match payload {
+            Some(payload) => {
+                let parsed = decode(&payload);
+                (payload, parsed)
+            }
+            None if self.torn => (
+                Vec::new(),
+                Err(ParseFailure::new(
+                    "Record is cut off by the end of the input",
+                )),
+            ),
+            None => return None,
+        };
+        Some(Ok(InputRecord {
+            line: Some(self.record),
+            raw: RawRecord::Binary(raw),
+            parsed,
+        }))
+    }
+}
+
+fn decode(payload: &[u8]) -> Result<TransactionRecord, ParseFailure> {
+    let mut fields = Fields(payload);
+    let flags = fields
+        .take_u8()
+        .ok_or_else(|| ParseFailure::new("Record is too short"))?;
+    let tx_type = fields
+        .take_u8()
+        .and_then(|len| fields.take(len as usize))
+        .ok_or_else(|| ParseFailure::new("Record is too short"))?;
+    let tx_type =
+        std::str::from_utf8(tx_type).map_err(|_| ParseFailure::new("Type is not valid UTF-8"))?;
+    let (Some(client), Some(tx)) = (fields.take_client(), fields.take_tx()) else {
+        return Err(ParseFailure::new("Record is too short"));
+    };
+
+    let failure = |message: String| ParseFailure {
+        message,
+        client: Some(client),
+        tx: Some(tx),
+    };
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(failure(format!("Unknown flags {flags:#010b}")));
+    }
+    let too_short = || failure("Record is too short".to_string());
+    let mut record_fields = RecordFields::default();
+    if flags & HAS_AMOUNT != 0 {
+        let units = fields.take_i64().ok_or_else(too_short)?;
+        let amount = PositiveAmount::try_from(Amount::from_units(units))
+            .map_err(|e| failure(e.to_string()))?;
+        record_fields.amount = Some(amount);
+    }
+    if flags & HAS_TIMESTAMP != 0 {
+        let secs = fields.take_i64().ok_or_else(too_short)?;
+        record_fields.timestamp = Some(Timestamp::from_secs(secs));
+    }
+    if flags & HAS_CURRENCY != 0 {
+        let code = fields.take(3).ok_or_else(too_short)?;
+        record_fields.currency = Some(parse_currency(code).map_err(failure)?);
+    }
+    if flags & HAS_REASON != 0 {
+        let reason = std::str::from_utf8(fields.0)
+            .map_err(|_| failure("Reason is not valid UTF-8".to_string()))?;
+        record_fields.reason = Some(reason.to_string());
+    } else if !fields.0.is_empty() {
+        let len = fields.0.len();
+        return Err(failure(format!("Unexpected {len} bytes after the fields")));
+    }
+
+    TransactionRecord::new(tx_type, client, tx, record_fields).map_err(|e| failure(e.to_string()))
+}
+
+fn parse_currency(code: &[u8]) -> Result<Currency, String> {
+    std::str::from_utf8(code)
+        .map_err(|_| "Currency is not valid UTF-8".to_string())?
+        .parse()
+        .map_err(|e: crate::errors::CurrencyError| e.to_string())
+}
+
+/// Cursor over the fields of a payload
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (field, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(field)
+    }
+
+    fn take_u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn take_i64(&mut self) -> Option<i64> {
+        let bytes = self.take(size_of::<i64>())?;
+        Some(i64::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn take_client(&mut self) -> Option<ClientId> {
+        let bytes = self.take(size_of::<ClientId>())?;
+        Some(ClientId::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn take_tx(&mut self) -> Option<TransactionId> {
+        let bytes = self.take(size_of::<TransactionId>())?;
+        Some(TransactionId::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+/// Encodes a record in the binary format, including the length prefix
+#[cfg(test)]
+pub fn encode(record: &TransactionRecord) -> Vec<u8> {
+    let amount = record.tx_type.amount().or(record.tx_type.limit());
+    let reason = record.tx_type.reason();
+    let flags = [
+        (amount.is_some(), HAS_AMOUNT),
+        (record.timestamp.is_some(), HAS_TIMESTAMP),
+        (record.currency.is_some(), HAS_CURRENCY),
+        (reason.is_some(), HAS_REASON),
+    ]
+    .into_iter()
+    .filter(|(present, _)| *present)
+    .fold(0, |flags, (_, flag)| flags | flag);
+
+    let tx_type = record.tx_type.to_string();
+    let mut payload = vec![flags, tx_type.len() as u8];
+    payload.extend_from_slice(tx_type.as_bytes());
+    payload.extend_from_slice(&record.client.to_le_bytes());
+    payload.extend_from_slice(&record.tx.to_le_bytes());
+    if let Some(amount) = amount {
+        payload.extend_from_slice(&amount.get().units().to_le_bytes());
+    }
+    if let Some(timestamp) = record.timestamp {
+        payload.extend_from_slice(&timestamp.secs().to_le_bytes());
+    }
+    if let Some(currency) = record.currency {
+        payload.extend_from_slice(&currency.as_bytes());
+    }
+    if let Some(reason) = reason {
+        payload.extend_from_slice(reason.as_bytes());
+    }
+
+    let mut encoded = (payload.len() as u16).to_le_bytes().to_vec();
+    encoded.extend(payload);
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("deposit", Some("1.5"), None; "deposit")]
+    #[test_case("withdrawal", Some("0.0001"), None; "withdrawal")]
+    #[test_case("dispute", None, None; "dispute")]
+    #[test_case("resolve", None, None; "resolve")]
+    #[test_case("chargeback", None, None; "chargeback")]
+    #[test_case("unlock", None, Some("Verified"); "unlock")]
+    #[test_case("limit", Some("250"), Some("Credit line"); "limit")]
+    #[test_case("limit", None, Some("Revoked"); "revoked limit")]
+    fn test_round_trip(tx_type: &str, amount: Option<&str>, reason: Option<&str>) {
+        for timestamp in [
+            None,
+            Some(Timestamp::from_secs(-1)),
+            Some(Timestamp::from_secs(1_700_000_000)),
+        ] {
+            for currency in [None, Some(Currency::USD)] {
+                let fields = RecordFields {
+                    amount: amount.map(|amount| amount.parse().unwrap()),
+                    reason: reason.map(str::to_string),
+                    timestamp,
+                    currency,
+                };
+                let record = TransactionRecord::new(tx_type, 3, 7, fields).unwrap();
+
+                let encoded = encode(&record);
+                let Ok(decoded) = decode(&encoded[2..]) else {
+                    panic!("{tx_type} record is not decoded");
+                };
+
+                assert_eq!(decoded.tx_type, record.tx_type);
+                assert_eq!((decoded.client, decoded.tx), (3, 7));
+                assert_eq!(decoded.timestamp, timestamp);
+                assert_eq!(decoded.currency, currency);
+            }
+        }
+    }
+
+    #[test_case(&[0x10, 7, b'd', b'i', b's', b'p', b'u', b't', b'e', 1, 0, 2, 0, 0, 0], "Unknown flags 0b00010000"; "unknown flag")]
+    #[test_case(&[HAS_TIMESTAMP, 7, b'd', b'i', b's', b'p', b'u', b't', b'e', 1, 0, 2, 0, 0, 0, 1, 2], "Record is too short"; "missing timestamp")]
+    #[test_case(&[0, 7, b'd', b'i', b's', b'p', b'u', b't', b'e', 1, 0, 2, 0, 0, 0, b'u', b's', b'd'], "Unexpected 3 bytes after the fields"; "currency without flag")]
+    #[test_case(&[0, 7, b'd', b'e', b'p', b'o', b's', b'i', b't', 1, 0, 2, 0, 0, 0], "Missing amount of a deposit"; "deposit without amount")]
+    fn test_decode_failure(payload: &[u8], expected: &str) {
+        let failure = decode(payload).err().unwrap();
+        assert_eq!(failure.message, expected);
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    io::{self, Read},
+    str::FromStr,
+};
+
+use csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter, Trim};
+
+use super::{InputRecord, ParseFailure, RawRecord};
+use crate::transaction_record::TransactionRecord;
+
+/// Records of a CSV input with a header, columns may be surrounded by whitespace
+pub struct CsvRecords {
+    records: StringRecordsIntoIter<Box<dyn Read>>,
+    headers: StringRecord,
+}
+
+impl CsvRecords {
+    pub fn new(reader: Box<dyn Read>) -> io::Result<Self> {
+        let mut csv_reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let headers = csv_reader.headers()?.clone();
+        Ok(Self {
+            records: csv_reader.into_records(),
+            headers,
+        })
+    }
+
+    /// Reads a single field of a record which could not be parsed as a whole
+    fn raw_field<T: FromStr>(&self, record: &StringRecord, name: &str) -> Option<T> {
+        let index = self.headers.iter().position(|header| header == name)?;
+        record.get(index)?.parse().ok()
+    }
+}
+
+impl Iterator for CsvRecords {
+    type Item = io::Result<InputRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Raw records are read first, so the rejected ones can be reported with their content
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => return Some(Err(e.into())),
+            Err(e) => {
+                return Some(Ok(InputRecord {
+                    line: e.position().map(|p| p.line()),
+                    raw: RawRecord::Text(String::new()),
+                    parsed: Err(ParseFailure::new(e)),
+                }))
+            }
+        };
+
+        let parsed = record
+            .deserialize::<TransactionRecord>(Some(&self.headers))
+            .map_err(|e| ParseFailure {
+                message: e.to_string(),
+                client: self.raw_field(&record, "client"),
+                tx: self.raw_field(&record, "tx"),
+            });
+        Some(Ok(InputRecord {
+            line: record.position().map(|p| p.line()),
+            raw: RawRecord::Csv(record),
+            parsed,
+        }))
+    }
+}
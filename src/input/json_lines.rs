@@ -0,0 +1,60 @@
+use std::io::{self, BufRead, BufReader, Lines, Read};
+
+use serde_json::Value;
+
+use super::{InputRecord, ParseFailure, RawRecord};
+use crate::transaction_record::TransactionRecord;
+
+/// Records of a JSON Lines input, one object per line, blank lines are skipped.
+/// Amounts are given as strings or numbers, both are parsed from their text
+/// without precision loss.
+pub struct JsonLines {
+    lines: Lines<BufReader<Box<dyn Read>>>,
+    line: u64,
+}
+
+impl JsonLines {
+    pub fn new(reader: Box<dyn Read>) -> Self {
+        Self {
+            lines: BufReader::new(reader).lines(),
+            line: 0,
+        }
+    }
+}
+
+impl Iterator for JsonLines {
+    type Item = io::Result<InputRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            self.line += 1;
+            if !line.trim().is_empty() {
+                break line;
+            }
+        };
+
+        let parsed = TransactionRecord::from_json(line.as_bytes()).map_err(|e| {
+            // Ids are reported if the line is a valid JSON object with valid ids
+            let value = serde_json::from_str::<Value>(&line).unwrap_or_default();
+            ParseFailure {
+                message: e.to_string(),
+                client: raw_id(&value, "client"),
+                tx: raw_id(&value, "tx"),
+            }
+        });
+        Some(Ok(InputRecord {
+            line: Some(self.line),
+            raw: RawRecord::Text(line),
+            parsed,
+        }))
+    }
+}
+
+/// Reads a single id of a record which could not be parsed as a whole
+fn raw_id<T: TryFrom<u64>>(value: &Value, name: &str) -> Option<T> {
+    value.get(name)?.as_u64()?.try_into().ok()
+}
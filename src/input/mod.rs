@@ -0,0 +1,137 @@
+use std::{
+    ffi::OsStr,
+    fmt::{self, Display},
+    fs::File,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use crate::{transaction_record::TransactionRecord, ClientId, TransactionId};
+
+mod binary;
+mod csv_records;
+mod json_lines;
+mod resequence;
+
+pub use resequence::Resequencer;
+
+#[cfg(test)]
+pub use binary::encode as encode_binary;
+
+/// Source of the input transaction records, `-` given on the command line stands for stdin
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputSource {
+    Stdin,
+    File(PathBuf),
+}
+
+impl InputSource {
+    pub fn open(&self) -> io::Result<Box<dyn Read>> {
+        match self {
+            InputSource::Stdin => Ok(Box::new(io::stdin().lock())),
+            InputSource::File(path) => Ok(Box::new(File::open(path)?)),
+        }
+    }
+}
+
+impl From<&OsStr> for InputSource {
+    fn from(value: &OsStr) -> Self {
+        if value == "-" {
+            InputSource::Stdin
+        } else {
+            InputSource::File(value.into())
+        }
+    }
+}
+
+impl Display for InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputSource::Stdin => write!(f, "<stdin>"),
+            InputSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Encoding of the input records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    /// CSV with a `type,client,tx,amount` header and optional `reason`, `timestamp`
+    /// and `currency` columns
+    Csv,
+    /// One JSON object per line, with the same fields as the CSV columns
+    Jsonl,
+    /// Length-prefixed binary records
+    Binary,
+}
+
+impl InputFormat {
+    /// Detects the format from the file extension, CSV is used if it is not recognized
+    pub fn detect(source: &InputSource) -> Self {
+        let extension = match source {
+            InputSource::File(path) => path.extension().and_then(|ext| ext.to_str()),
+            InputSource::Stdin => None,
+        };
+        match extension {
+            Some("jsonl" | "ndjson") => InputFormat::Jsonl,
+            Some("bin") => InputFormat::Binary,
+            _ => InputFormat::Csv,
+        }
+    }
+
+    /// Reads the records in the format. An error of the iterator means the input
+    /// cannot be read any further, a record that could not be parsed is returned
+    /// as an `InputRecord` with the parse failure.
+    pub fn records(
+        self,
+        reader: Box<dyn Read>,
+    ) -> io::Result<Box<dyn Iterator<Item = io::Result<InputRecord>>>> {
+        Ok(match self {
+            InputFormat::Csv => Box::new(csv_records::CsvRecords::new(reader)?),
+            InputFormat::Jsonl => Box::new(json_lines::JsonLines::new(reader)),
+            InputFormat::Binary => Box::new(binary::BinaryRecords::new(reader)),
+        })
+    }
+}
+
+/// Single record read from an input, parsed or not
+pub struct InputRecord {
+    /// Line of the record in the input, or its sequence number in a binary input
+    pub line: Option<u64>,
+    pub raw: RawRecord,
+    pub parsed: Result<TransactionRecord, ParseFailure>,
+}
+
+/// Content of the record as it was read, formatted only when the record is reported
+pub enum RawRecord {
+    Csv(::csv::StringRecord),
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Display for RawRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawRecord::Csv(record) => write!(f, "{}", record.iter().collect::<Vec<_>>().join(",")),
+            RawRecord::Text(text) => write!(f, "{text}"),
+            RawRecord::Binary(bytes) => bytes.iter().try_for_each(|b| write!(f, "{b:02x}")),
+        }
+    }
+}
+
+/// Reason why a record could not be parsed, with the ids that could be read from it
+pub struct ParseFailure {
+    pub message: String,
+    pub client: Option<ClientId>,
+    pub tx: Option<TransactionId>,
+}
+
+impl ParseFailure {
+    fn new(message: impl ToString) -> Self {
+        Self {
+            message: message.to_string(),
+            client: None,
+            tx: None,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{cmp::Reverse, collections::BinaryHeap, io};
+
+use super::InputRecord;
+use crate::timestamp::Timestamp;
+
+/// Reorders the records of an input by their timestamps. At most `capacity` records
+/// are held back, so a record can be moved before at most that many preceding records,
+/// records delayed any further stay out of order.
+///
+/// Records without a timestamp, including the ones that could not be parsed, are ordered
+/// by the latest timestamp read before them, so they stay next to their neighbours.
+/// Records with equal timestamps keep the input order.
+pub struct Resequencer<I> {
+    records: I,
+    pending: BinaryHeap<Reverse<Pending>>,
+    capacity: usize,
+    latest: Option<Timestamp>,
+    arrival: u64,
+    exhausted: bool,
+}
+
+struct Pending {
+    key: (Option<Timestamp>, u64),
+    record: InputRecord,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl<I> Resequencer<I> {
+    pub fn new(records: I, capacity: usize) -> Self {
+        Self {
+            records,
+            pending: BinaryHeap::new(),
+            capacity,
+            latest: None,
+            arrival: 0,
+            exhausted: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<InputRecord>>> Iterator for Resequencer<I> {
+    type Item = io::Result<InputRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.exhausted && self.pending.len() <= self.capacity {
+            match self.records.next() {
+                Some(Ok(record)) => {
+                    let timestamp = record.parsed.as_ref().ok().and_then(|tx| tx.timestamp);
+                    self.latest = self.latest.max(timestamp);
+                    self.pending.push(Reverse(Pending {
+                        key: (timestamp.or(self.latest), self.arrival),
+                        record,
+                    }));
+                    self.arrival += 1;
+                }
+                // The input cannot be read any further, the held records are dropped
+                Some(Err(e)) => return Some(Err(e)),
+                None => self.exhausted = true,
+            }
+        }
+        self.pending
+            .pop()
+            .map(|Reverse(pending)| Ok(pending.record))
+    }
+}
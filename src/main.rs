@@ -1,49 +1,360 @@
-use std::path::Path;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
 
 use anyhow::Context;
 use clap::Parser;
-use config::Config;
-use csv::{ReaderBuilder, Writer};
-use engine::TxEngine;
+use config::{Command, Config, ConvertConfig, StorageArgs, ValidateConfig};
+use conversion::{convert_balances, read_rates, write_conversion};
+use engine::{DiskStorage, EngineOptions, Journal, ShardedEngine, TxEngine};
+use errors::ProcessingError;
+use http::HttpServer;
+use input::{InputFormat, InputRecord, InputSource, Resequencer};
+use output::write_clients;
+use rejections::{RecordOrigin, Rejection, Rejections, PARSE_ERROR_CODE};
+use server::Server;
+use statement::{build_statement, write_statement};
+use transaction_record::TransactionRecord;
+use validate::{diff_balances, read_expected_balances, validate, write_summary};
 
+mod amount;
 mod config;
+mod conversion;
+mod currency;
 mod engine;
 mod errors;
+mod http;
+mod input;
+mod output;
+mod rejections;
+mod server;
+mod statement;
 #[cfg(test)]
 mod tests;
+mod timestamp;
 mod transaction_record;
+mod validate;
 
 // Type aliases for easier switching between different types
 type ClientId = u16;
 type TransactionId = u32;
-type Float = f64;
 
 fn main() -> anyhow::Result<()> {
     let config = Config::parse();
-    process_file(&config.input_file_path, std::io::stdout())
-}
-
-fn process_file(file_name: impl AsRef<Path>, writer: impl std::io::Write) -> anyhow::Result<()> {
-    let mut engine = TxEngine::default();
-    let mut csv_reader = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(file_name)
-        .context("Failed to open CSV file")?;
-    let transactions_iter = csv_reader.deserialize();
-
-    transactions_iter.for_each(|tx| match tx {
-        Ok(tx) => engine
-            .process_tx(tx)
-            .unwrap_or_else(|e| eprintln!("Failed to process transaction: {e}")),
-        Err(e) => eprintln!("Failed to parse transaction: {e}"),
-    });
-
-    let mut writer = Writer::from_writer(writer);
-    engine.get_clients().for_each(|client| {
-        writer.serialize(client).unwrap_or_else(|e| {
-            eprintln!("Failed to serialize client: {e}");
+    match config.command {
+        Some(Command::Serve(serve_config)) => {
+            let mut engine = TxEngine::new(serve_config.engine.options()?);
+            if let Some(path) = &serve_config.journal {
+                recover_journal(&mut engine, path)?;
+            }
+            Server::bind(serve_config.listen, engine, serve_config.max_connections)?.run()
+        }
+        Some(Command::Http(http_config)) => {
+            let mut engine = TxEngine::new(http_config.engine.options()?);
+            if let Some(path) = &http_config.journal {
+                recover_journal(&mut engine, path)?;
+            }
+            HttpServer::bind(http_config.listen, engine, http_config.workers)?.run()
+        }
+        Some(Command::Statement(statement_config)) => {
+            let mut engine = TxEngine::new(statement_config.engine.options()?);
+            if let Some(path) = &statement_config.load_snapshot {
+                engine = load_snapshot(path, engine)?;
+            }
+            let entries = build_statement(
+                &mut engine,
+                statement_config.client,
+                &statement_config.inputs,
+                statement_config.input_format,
+            )?;
+            write_statement(&entries, BufWriter::new(std::io::stdout()))
+        }
+        Some(Command::Validate(validate_config)) => run_validate(validate_config),
+        Some(Command::Convert(convert_config)) => run_convert(convert_config),
+        None => run_batch(config),
+    }
+}
+
+/// Dry run of the inputs, fails if the balances differ from the expected ones
+fn run_validate(config: ValidateConfig) -> anyhow::Result<()> {
+    let mut engine = new_engine(config.engine.options()?, &config.storage)?;
+    if let Some(path) = &config.load_snapshot {
+        engine = load_snapshot(path, engine)?;
+    }
+    let mut summary = validate(&mut engine, &config.inputs, config.input_format)?;
+    if let Some(path) = &config.expected {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open expected balances {}", path.display()))?;
+        let expected = read_expected_balances(BufReader::new(file))
+            .with_context(|| format!("Failed to read expected balances {}", path.display()))?;
+        summary.balance_diffs = Some(diff_balances(&engine, &expected));
+    }
+    write_summary(&summary, BufWriter::new(std::io::stdout()))?;
+
+    match &summary.balance_diffs {
+        Some(diffs) if !diffs.is_empty() => {
+            anyhow::bail!("{} balances differ from the expected ones", diffs.len())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Converts the balances into the base currency, nothing is saved
+fn run_convert(config: ConvertConfig) -> anyhow::Result<()> {
+    let file = File::open(&config.rates)
+        .with_context(|| format!("Failed to open rates {}", config.rates.display()))?;
+    let rates = read_rates(BufReader::new(file), config.base_currency)
+        .with_context(|| format!("Failed to read rates {}", config.rates.display()))?;
+
+    let mut engine = new_engine(config.engine.options()?, &config.storage)?;
+    if let Some(path) = &config.load_snapshot {
+        engine = load_snapshot(path, engine)?;
+    }
+    process_inputs(
+        &mut engine,
+        &config.inputs,
+        config.input_format,
+        &mut Rejections::default(),
+    )?;
+    let rows = convert_balances(&engine, &rates, config.rounding)?;
+    write_conversion(&rows, BufWriter::new(std::io::stdout()))
+}
+
+fn run_batch(config: Config) -> anyhow::Result<()> {
+    let mut engine = new_engine(config.engine.options()?, &config.storage)?;
+    if let Some(path) = &config.load_snapshot {
+        engine = load_snapshot(path, engine)?;
+    }
+    if let Some(path) = &config.journal {
+        recover_journal(&mut engine, path)?;
+    }
+    let mut rejections = config.rejections()?;
+
+    if config.threads > 1 {
+        engine = process_inputs_sharded(
+            engine,
+            config.threads as usize,
+            &config.inputs,
+            config.input_format,
+            &mut rejections,
+        )?;
+    } else {
+        process_inputs(
+            &mut engine,
+            &config.inputs,
+            config.input_format,
+            &mut rejections,
+        )?;
+    }
+    rejections.flush()?;
+    engine
+        .check_ledger()
+        .context("Ledger invariant violated, the state is not saved")?;
+    if let Some(path) = &config.save_snapshot {
+        save_snapshot(&engine, path)?;
+        // The snapshot already contains the effects of all journaled records
+        if let Some(journal) = engine.journal_mut() {
+            journal.reset().context("Failed to reset journal")?;
+        }
+    } else if let Some(journal) = engine.journal_mut() {
+        journal.sync().context("Failed to sync journal")?;
+    }
+    write_clients(
+        &engine,
+        config.sort,
+        config.output_format,
+        BufWriter::new(std::io::stdout()),
+    )
+}
+
+/// Rebuilds the engine state from the journal, then keeps journaling new records
+fn recover_journal(engine: &mut TxEngine, path: &Path) -> anyhow::Result<()> {
+    let (journal, summary) = Journal::recover(path, engine)
+        .with_context(|| format!("Failed to recover journal {}", path.display()))?;
+    if summary.truncated_bytes > 0 {
+        eprintln!(
+            "{}: Truncated {} bytes of a torn journal entry",
+            path.display(),
+            summary.truncated_bytes
+        );
+    }
+    for rejected in &summary.rejected {
+        eprintln!(
+            "{}: Journaled record at byte {} was rejected during the replay: {}",
+            path.display(),
+            rejected.offset,
+            rejected.error
+        );
+    }
+    engine.attach_journal(journal);
+    Ok(())
+}
+
+/// Engine keeping the committed transactions in memory, or in a spill file if configured
+fn new_engine(options: EngineOptions, storage: &StorageArgs) -> anyhow::Result<TxEngine> {
+    match &storage.tx_spill_file {
+        Some(path) => {
+            let storage = DiskStorage::create(path, storage.tx_cache_size)
+                .with_context(|| format!("Failed to create spill file {}", path.display()))?;
+            Ok(TxEngine::with_storage(options, Box::new(storage)))
+        }
+        None => Ok(TxEngine::new(options)),
+    }
+}
+
+/// Restores the snapshot into the given empty engine
+fn load_snapshot(path: &Path, engine: TxEngine) -> anyhow::Result<TxEngine> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open snapshot {}", path.display()))?;
+    engine
+        .restore_snapshot(BufReader::new(file))
+        .with_context(|| format!("Failed to load snapshot {}", path.display()))
+}
+
+/// The snapshot is written to a temporary file first, so a failed write
+/// never replaces the previous snapshot
+fn save_snapshot(engine: &TxEngine, path: &Path) -> anyhow::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let save = || -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        engine.save_snapshot(&mut writer)?;
+        writer.flush()?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    };
+    save().with_context(|| format!("Failed to save snapshot {}", path.display()))
+}
+
+/// Feeds all inputs into one engine in the given order
+fn process_inputs(
+    engine: &mut TxEngine,
+    inputs: &[InputSource],
+    format: Option<InputFormat>,
+    rejections: &mut Rejections,
+) -> anyhow::Result<()> {
+    let resequence = engine.options().resequence_buffer();
+    for input in inputs {
+        read_input(
+            input,
+            format,
+            resequence,
+            rejections,
+            |tx, origin, rejections| {
+                let (client, tx_id) = (tx.client, tx.tx);
+                match engine.process_tx(tx) {
+                    Ok(()) => Ok(()),
+                    // Records must not be accepted without being journaled and stored
+                    Err(e) if e.is_fatal() => Err(e.into()),
+                    Err(e) => rejections.report(Rejection::processing(origin(), client, tx_id, &e)),
+                }
+            },
+        )
+        .with_context(|| format!("Failed to process {input}"))?;
+    }
+    Ok(())
+}
+
+/// Same as `process_inputs`, but records are processed in parallel by engines sharded
+/// by the client id. Rejections are reported as soon as the workers produce them,
+/// so their order may differ from the input order.
+fn process_inputs_sharded(
+    engine: TxEngine,
+    shards: usize,
+    inputs: &[InputSource],
+    format: Option<InputFormat>,
+    rejections: &mut Rejections,
+) -> anyhow::Result<TxEngine> {
+    let resequence = engine.options().resequence_buffer();
+    let mut sharded = ShardedEngine::new(engine, shards)?;
+    for input in inputs {
+        read_input(
+            input,
+            format,
+            resequence,
+            rejections,
+            |tx, origin, rejections| {
+                let context = (origin(), tx.client, tx.tx);
+                sharded.process_tx(tx, context);
+                report_sharded_errors(sharded.take_errors(), rejections)
+            },
+        )
+        .with_context(|| format!("Failed to process {input}"))?;
+    }
+
+    let (engine, errors) = sharded.finish()?;
+    report_sharded_errors(errors, rejections)?;
+    Ok(engine)
+}
+
+type ShardedContext = (RecordOrigin, ClientId, TransactionId);
+
+/// Reports the records rejected by the workers, a fatal error of a worker stops
+/// the processing the same way as in `process_inputs`
+fn report_sharded_errors(
+    errors: Vec<(ShardedContext, ProcessingError)>,
+    rejections: &mut Rejections,
+) -> anyhow::Result<()> {
+    errors
+        .into_iter()
+        .try_for_each(|((origin, client, tx), e)| {
+            if e.is_fatal() {
+                return Err(e.into());
+            }
+            rejections.report(Rejection::processing(origin, client, tx, &e))
         })
-    });
+}
+
+/// Reads the records of the input and passes the parsed ones to the processing function.
+/// Without an explicit format, it is detected from the file extension. With a resequence
+/// buffer size, the records are reordered by their timestamps before they are processed.
+fn read_input(
+    input: &InputSource,
+    format: Option<InputFormat>,
+    resequence: Option<usize>,
+    rejections: &mut Rejections,
+    mut process: impl FnMut(
+        TransactionRecord,
+        &dyn Fn() -> RecordOrigin,
+        &mut Rejections,
+    ) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let reader = input.open().context("Failed to open input")?;
+    let format = format.unwrap_or_else(|| InputFormat::detect(input));
+    let source: Arc<str> = input.to_string().into();
+
+    let mut records = format.records(reader)?;
+    if let Some(capacity) = resequence {
+        records = Box::new(Resequencer::new(records, capacity));
+    }
+    for record in records {
+        let InputRecord { line, raw, parsed } = record?;
+        match parsed {
+            Ok(tx) => {
+                // Only built when needed, most of the records are never reported
+                let timestamp = tx.timestamp;
+                let origin = || RecordOrigin {
+                    source: source.clone(),
+                    line,
+                    raw: raw.to_string(),
+                    timestamp,
+                };
+                process(tx, &origin, rejections)?
+            }
+            Err(failure) => rejections.report(Rejection {
+                source: source.clone(),
+                line,
+                raw: raw.to_string(),
+                client: failure.client,
+                tx: failure.tx,
+                timestamp: None,
+                code: PARSE_ERROR_CODE,
+                message: failure.message,
+            })?,
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,98 @@
+use std::io::Write;
+
+use crate::engine::{ClientBalance, ClientOrder, TxEngine};
+
+/// Format of the client balance report, with a row for each client and currency.
+/// Amounts are rendered the same way in all formats, with up to 4 decimal places
+/// and trailing zeros trimmed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// CSV with a `client,currency,available,held,total,locked,overdrawn` header
+    #[default]
+    Csv,
+    /// A single JSON array of client balances, amounts are strings
+    Json,
+    /// One JSON object per client balance and line, amounts are strings
+    Jsonl,
+    /// Aligned columns for reading in a terminal
+    Table,
+}
+
+const HEADERS: [&str; 7] = [
+    "client",
+    "currency",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "overdrawn",
+];
+
+pub fn write_clients(
+    engine: &TxEngine,
+    order: ClientOrder,
+    format: OutputFormat,
+    mut writer: impl Write,
+) -> anyhow::Result<()> {
+    let balances = engine.get_balances(order);
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut writer);
+            for balance in balances {
+                writer.serialize(balance)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &balances.collect::<Vec<_>>())?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Jsonl => {
+            for balance in balances {
+                serde_json::to_writer(&mut writer, &balance)?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Table => write_table(balances.map(table_row).collect(), &mut writer)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn table_row(balance: ClientBalance) -> [String; 7] {
+    [
+        balance.id().to_string(),
+        balance.currency().to_string(),
+        balance.available().to_string(),
+        balance.held().to_string(),
+        balance.total().to_string(),
+        balance.is_locked().to_string(),
+        balance.is_overdrawn().to_string(),
+    ]
+}
+
+/// Columns are as wide as their longest value, numbers are aligned to the right
+fn write_table(rows: Vec<[String; 7]>, writer: &mut impl Write) -> std::io::Result<()> {
+    let mut widths = HEADERS.map(str::len);
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len());
+        }
+    }
+
+    let header = HEADERS.map(String::from);
+    let separator = widths.map(|width| "-".repeat(width));
+    for row in [&header, &separator].into_iter().chain(&rows) {
+        let line: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (value, width))| match column {
+                1 | 5 | 6 => format!("{value:<width$}"),
+                _ => format!("{value:>width$}"),
+            })
+            .collect();
+        writeln!(writer, "{}", line.join("  ").trim_end())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{errors::ProcessingError, timestamp::Timestamp, ClientId, TransactionId};
+
+/// Code used for records that could not be parsed into a transaction
+pub const PARSE_ERROR_CODE: &str = "parse_error";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RejectionFormat {
+    Csv,
+    Jsonl,
+}
+
+impl RejectionFormat {
+    /// Detects the format from the file extension, CSV is used if it is not recognized
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "json") => RejectionFormat::Jsonl,
+            _ => RejectionFormat::Csv,
+        }
+    }
+}
+
+/// Location and content of an input record, used to report it if it is rejected
+#[derive(Debug, Clone)]
+pub struct RecordOrigin {
+    pub source: Arc<str>,
+    pub line: Option<u64>,
+    pub raw: String,
+    /// Timestamp of the parsed record, if it has one
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Single entry of the rejection report, describing an input record that was dropped
+#[derive(Debug, Serialize)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct Rejection {
+    pub source: Arc<str>,
+    pub line: Option<u64>,
+    pub raw: String,
+    pub client: Option<ClientId>,
+    pub tx: Option<TransactionId>,
+    pub timestamp: Option<Timestamp>,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn processing(
+        origin: RecordOrigin,
+        client: ClientId,
+        tx: TransactionId,
+        error: &ProcessingError,
+    ) -> Self {
+        Self {
+            source: origin.source,
+            line: origin.line,
+            raw: origin.raw,
+            client: Some(client),
+            tx: Some(tx),
+            timestamp: origin.timestamp,
+            code: error.code(),
+            message: error.to_string(),
+        }
+    }
+}
+
+enum RejectionWriter {
+    Csv(Box<csv::Writer<Box<dyn Write>>>),
+    JsonLines(Box<dyn Write>),
+}
+
+/// Collects rejected records. Each rejection is always printed to stderr
+/// and, if configured, written to a rejection report file.
+#[derive(Default)]
+pub struct Rejections {
+    sink: Option<RejectionWriter>,
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl Rejections {
+    pub fn create(path: &Path, format: RejectionFormat) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create rejection report {}", path.display()))?;
+        Ok(Self::from_writer(BufWriter::new(file), format))
+    }
+
+    pub fn from_writer(writer: impl Write + 'static, format: RejectionFormat) -> Self {
+        let writer: Box<dyn Write> = Box::new(writer);
+        let sink = match format {
+            RejectionFormat::Csv => {
+                RejectionWriter::Csv(Box::new(csv::Writer::from_writer(writer)))
+            }
+            RejectionFormat::Jsonl => RejectionWriter::JsonLines(writer),
+        };
+        Self {
+            sink: Some(sink),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn report(&mut self, rejection: Rejection) -> anyhow::Result<()> {
+        let line = rejection.line.map(|l| format!(":{l}")).unwrap_or_default();
+        let time = rejection
+            .timestamp
+            .map(|t| format!(" at {t}"))
+            .unwrap_or_default();
+        eprintln!(
+            "{}{line}: Rejected transaction{time} ({}): {}",
+            rejection.source, rejection.code, rejection.message
+        );
+        *self.counts.entry(rejection.code).or_default() += 1;
+
+        match &mut self.sink {
+            Some(RejectionWriter::Csv(writer)) => writer.serialize(&rejection)?,
+            Some(RejectionWriter::JsonLines(writer)) => {
+                serde_json::to_writer(&mut *writer, &rejection)?;
+                writer.write_all(b"\n")?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Number of the reported rejections by their error code
+    pub fn counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.counts
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        match &mut self.sink {
+            Some(RejectionWriter::Csv(writer)) => writer.flush()?,
+            Some(RejectionWriter::JsonLines(writer)) => writer.flush()?,
+            None => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{
+    engine::{Client, TxEngine},
+    errors::ProcessingError,
+    rejections::PARSE_ERROR_CODE,
+    transaction_record::TransactionRecord,
+    ClientId,
+};
+
+/// Long-lived engine accepting transaction records over TCP.
+///
+/// The protocol is line based, every request line is answered with a single JSON line:
+/// - `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}` - a record as a JSON object
+/// - `deposit, 1, 1, 1.5` - a record as a CSV row with the
+///   `type,client,tx,amount,reason,timestamp,currency` columns, the trailing ones are optional
+/// - `balance 1` - a query for the current balance of the client
+///
+/// Each connection is handled by its own thread, all of them share one engine. Connections
+/// over the maximum are answered with a `too_many_connections` error and closed, and so are
+/// connections sending a line longer than [`MAX_LINE_LENGTH`], with a `line_too_long` error.
+pub struct Server {
+    listener: TcpListener,
+    engine: Arc<Mutex<TxEngine>>,
+    max_connections: usize,
+    /// Number of the connections being handled
+    open: Arc<AtomicUsize>,
+}
+
+/// Longest accepted request line in bytes, without the newline
+pub const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Response<'a> {
+    Ok {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        client: Option<&'a Client>,
+    },
+    Error {
+        code: &'static str,
+        message: String,
+    },
+}
+
+impl Response<'_> {
+    fn error(code: &'static str, message: impl ToString) -> Self {
+        Response::Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Server {
+    pub fn bind(addr: SocketAddr, engine: TxEngine, max_connections: u16) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr).with_context(|| format!("Failed to bind {addr}"))?;
+        Ok(Self {
+            listener,
+            engine: Arc::new(Mutex::new(engine)),
+            max_connections: max_connections.max(1).into(),
+            open: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(self) -> anyhow::Result<()> {
+        eprintln!("Listening on {}", self.local_addr()?);
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Failed to accept connection: {e}");
+                    continue;
+                }
+            };
+            let Some(slot) = ConnectionSlot::acquire(&self.open, self.max_connections) else {
+                refuse_connection(stream, self.max_connections);
+                continue;
+            };
+            let engine = self.engine.clone();
+            thread::spawn(move || {
+                let _slot = slot;
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = handle_connection(stream, &engine) {
+                    eprintln!("Connection {peer:?} failed: {e}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Counted open connection, released when the connection is closed
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn acquire(open: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+            (count < max).then_some(count + 1)
+        })
+        .ok()
+        .map(|_| Self(open.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Tells the client why the connection is closed, a failure to do so is only logged
+fn refuse_connection(mut stream: TcpStream, max_connections: usize) {
+    let response = Response::error(
+        "too_many_connections",
+        format!("Server is handling the maximum of {max_connections} connections"),
+    );
+    if let Err(e) = write_response(&mut stream, &response) {
+        eprintln!("Failed to refuse connection: {e}");
+    }
+}
+
+fn handle_connection(stream: TcpStream, engine: &Mutex<TxEngine>) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        // The newline is read on top of the maximal length
+        let limit = MAX_LINE_LENGTH as u64 + 1;
+        if (&mut reader).take(limit).read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        if line.len() > MAX_LINE_LENGTH && line.last() != Some(&b'\n') {
+            let response = Response::error(
+                "line_too_long",
+                format!("Line is longer than {MAX_LINE_LENGTH} bytes"),
+            );
+            write_response(&mut writer, &response)?;
+            writer.flush()?;
+            return Ok(());
+        }
+        match std::str::from_utf8(&line) {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => handle_line(line, engine, &mut writer)?,
+            Err(_) => write_response(
+                &mut writer,
+                &Response::error(PARSE_ERROR_CODE, "Line is not valid UTF-8"),
+            )?,
+        }
+        writer.flush()?;
+    }
+}
+
+fn handle_line(
+    line: &str,
+    engine: &Mutex<TxEngine>,
+    writer: &mut impl Write,
+) -> anyhow::Result<()> {
+    let line = line.trim();
+
+    if let Some(client) = line.strip_prefix("balance") {
+        let engine = match lock(engine) {
+            Ok(engine) => engine,
+            Err(response) => return write_response(writer, &response),
+        };
+        let response = match client.trim().parse::<ClientId>() {
+            Ok(id) => match engine.get_client(id) {
+                Some(client) => Response::Ok {
+                    client: Some(client),
+                },
+                None => Response::error("client_not_found", format!("Client {id} not found")),
+            },
+            Err(e) => Response::error(PARSE_ERROR_CODE, format!("Invalid client id: {e}")),
+        };
+        return write_response(writer, &response);
+    }
+
+    let record = if line.starts_with('{') {
+        TransactionRecord::from_json(line.as_bytes()).map_err(|e| e.to_string())
+    } else {
+        TransactionRecord::from_csv_row(line).map_err(|e| e.to_string())
+    };
+    let response = match record {
+        Ok(record) => match lock(engine) {
+            Ok(mut engine) => match engine.process_tx(record) {
+                Ok(()) => Response::Ok { client: None },
+                Err(e) => processing_error(&e),
+            },
+            Err(response) => response,
+        },
+        Err(message) => Response::error(PARSE_ERROR_CODE, message),
+    };
+    write_response(writer, &response)
+}
+
+/// Locks the shared engine. A connection that panicked while holding the lock may have left
+/// the engine half-updated, so it's not used anymore and every line is answered with an error.
+fn lock(engine: &Mutex<TxEngine>) -> Result<MutexGuard<'_, TxEngine>, Response<'static>> {
+    engine.lock().map_err(|_| {
+        Response::error(
+            "internal_error",
+            "Engine is unavailable after a failure of another connection",
+        )
+    })
+}
+
+fn processing_error(error: &ProcessingError) -> Response<'static> {
+    Response::error(error.code(), error)
+}
+
+fn write_response(writer: &mut impl Write, response: &Response) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *writer, response)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poisoned_engine_is_reported() {
+        let engine = Mutex::new(TxEngine::default());
+        thread::scope(|scope| {
+            let holder = scope.spawn(|| {
+                let _engine = engine.lock().unwrap();
+                panic!("Connection failed while holding the engine");
+            });
+            assert!(holder.join().is_err());
+        });
+
+        for line in ["deposit,1,1,1", "balance 1"] {
+            let mut response = Vec::new();
+            handle_line(line, &engine, &mut response).unwrap();
+            let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+            assert_eq!(response["code"], "internal_error", "{line}");
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{collections::HashMap, io::Write, sync::Arc};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{
+    amount::Amount,
+    currency::Currency,
+    engine::{TransactionState, TxEngine},
+    input::{InputFormat, InputSource},
+    read_input,
+    rejections::Rejections,
+    timestamp::Timestamp,
+    ClientId, TransactionId,
+};
+
+/// Code of the statement entries for accepted records
+const ACCEPTED: &str = "accepted";
+
+/// Single row of a client statement - a record of the client with its result
+/// and the balances right after it
+#[derive(Debug, Serialize)]
+pub struct StatementEntry {
+    pub source: Arc<str>,
+    pub line: Option<u64>,
+    pub timestamp: Option<Timestamp>,
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub tx: Option<TransactionId>,
+    /// Amount of the record, the credit limit of a limit record, or the amount
+    /// of the referred transaction for disputes and their settlements
+    pub amount: Option<Amount>,
+    /// Currency of the amount, the balances are the ones in this currency
+    pub currency: Currency,
+    /// `accepted`, or the code of the error the record was rejected with
+    pub result: &'static str,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    /// State of the referred transaction once all records are processed
+    pub state: Option<TransactionState>,
+    /// Audit reason of an admin record
+    pub reason: Option<String>,
+}
+
+impl StatementEntry {
+    fn new(
+        engine: &TxEngine,
+        client: ClientId,
+        currency: Currency,
+        source: Arc<str>,
+        tx_type: String,
+    ) -> Self {
+        let client = engine.get_client(client);
+        let balance = client.map(|c| c.balance(currency)).unwrap_or_default();
+        Self {
+            source,
+            line: None,
+            timestamp: None,
+            tx_type,
+            tx: None,
+            amount: None,
+            currency,
+            result: ACCEPTED,
+            available: balance.available(),
+            held: balance.held(),
+            total: balance.total(),
+            locked: client.is_some_and(|c| c.is_locked()),
+            state: None,
+            reason: None,
+        }
+    }
+}
+
+/// Processes the inputs and collects the chronological ledger of a single client.
+/// Records of other clients are processed too, as they may use transaction ids
+/// referred to by the client, but they are not part of the statement.
+/// If the client already exists in the engine, the statement starts with its opening balances,
+/// one for each currency. Each entry shows the balances in the currency of its record.
+pub fn build_statement(
+    engine: &mut TxEngine,
+    client: ClientId,
+    inputs: &[InputSource],
+    format: Option<InputFormat>,
+) -> anyhow::Result<Vec<StatementEntry>> {
+    let mut entries = Vec::new();
+    if let Some(opening) = engine.get_client(client) {
+        for (currency, _) in opening.balances() {
+            entries.push(StatementEntry::new(
+                engine,
+                client,
+                currency,
+                "".into(),
+                "opening".into(),
+            ));
+        }
+    }
+
+    // Only records that could not be parsed are reported, rejections of the client's
+    // records are part of the statement
+    let mut rejections = Rejections::default();
+    let resequence = engine.options().resequence_buffer();
+    for input in inputs {
+        read_input(
+            input,
+            format,
+            resequence,
+            &mut rejections,
+            |tx, origin, _| {
+                if tx.client != client {
+                    // Rejections of other clients are not reported, failures of the engine are
+                    return match engine.process_tx(tx) {
+                        Err(e) if e.is_fatal() => Err(e.into()),
+                        _ => Ok(()),
+                    };
+                }
+
+                let (tx_type, tx_id) = (tx.tx_type.to_string(), tx.tx);
+                let amount = tx.tx_type.amount().or(tx.tx_type.limit());
+                let reason = tx.tx_type.reason().map(String::from);
+                // Records referring to a transaction of the client are in its currency
+                let referred = if amount.is_some() || tx.tx_type.is_admin() {
+                    None
+                } else {
+                    engine
+                        .get_transaction(tx_id)?
+                        .filter(|referred| referred.client_id() == client)
+                };
+                let currency = referred
+                    .as_ref()
+                    .map_or_else(|| engine.record_currency(&tx), |tx| tx.currency());
+                let result = match engine.process_tx(tx) {
+                    Err(e) if e.is_fatal() => return Err(e.into()),
+                    result => result,
+                };
+                let origin = origin();
+                let mut entry =
+                    StatementEntry::new(engine, client, currency, origin.source, tx_type);
+                entry.line = origin.line;
+                entry.timestamp = origin.timestamp;
+                entry.tx = Some(tx_id);
+                entry.reason = reason;
+                entry.amount = amount
+                    .map(|amount| amount.get())
+                    .or(referred.map(|referred| referred.get_amount()));
+                if let Err(e) = result {
+                    entry.result = e.code();
+                }
+                entries.push(entry);
+                Ok(())
+            },
+        )?;
+    }
+
+    engine.check_ledger().context("Ledger invariant violated")?;
+    let states = engine
+        .client_transactions(client)
+        .map(|tx| tx.map(|tx| (tx.id(), tx.state())))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    for entry in &mut entries {
+        entry.state = entry.tx.and_then(|id| states.get(&id).copied());
+    }
+    Ok(entries)
+}
+
+pub fn write_statement(entries: &[StatementEntry], writer: impl Write) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for entry in entries {
+        writer.serialize(entry)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
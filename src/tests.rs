@@ -1,20 +1,1015 @@
-use std::collections::HashSet;
-
 use test_case::test_case;
 
-use crate::process_file;
+use clap::Parser;
+
+use crate::{
+    config::Config,
+    conversion::{convert_balances, read_rates, write_conversion, Rounding},
+    currency::Currency,
+    engine::{
+        ClientOrder, EngineOptions, Journal, OutOfOrderPolicy, TxEngine, WithdrawalDisputePolicy,
+    },
+    errors::{ConversionError, ProcessingError},
+    input::{encode_binary, InputFormat, InputSource},
+    output::{write_clients, OutputFormat},
+    process_inputs,
+    rejections::{RecordOrigin, RejectionFormat, Rejections},
+    report_sharded_errors,
+    statement::{build_statement, write_statement},
+    transaction_record::TransactionRecord,
+    validate::{diff_balances, read_expected_balances, validate, BalanceDiff},
+    TransactionId,
+};
+
+fn input(file_name: &str) -> InputSource {
+    InputSource::File(format!("./test_files/{file_name}").into())
+}
+
+fn run(inputs: &[InputSource], options: EngineOptions) -> anyhow::Result<String> {
+    let mut engine = TxEngine::new(options);
+    process_inputs(&mut engine, inputs, None, &mut Rejections::default())?;
+    Ok(output(&engine, ClientOrder::default()))
+}
+
+fn output(engine: &TxEngine, order: ClientOrder) -> String {
+    let mut buf = Vec::new();
+    write_clients(engine, order, OutputFormat::Csv, &mut buf).unwrap();
+    String::from_utf8(buf).expect("Invalid UTF-8")
+}
 
-#[test_case("file_without_spaces.csv", ["1,1.5,0,1.5,false", "2,2,0,2,false"]; "file without spaces")]
-#[test_case("type_case_insensitivity.csv", ["1,1.5,0,1.5,false", "2,2,0,2,false"]; "type case insensitivity")]
-#[test_case("file_with_spaces.csv", ["1,1.5,0,1.5,false", "2,2,0,2,false"]; "file with spaces")]
-#[test_case("precision_up_to_4_decimal.csv", ["1,2000000000.1235,0,2000000000.1235,false"]; "precision up to 4 decimal")]
+#[test_case("file_without_spaces.csv", ["1,EUR,1.5,0,1.5,false,false", "2,EUR,2,0,2,false,false"]; "file without spaces")]
+#[test_case("type_case_insensitivity.csv", ["1,EUR,1.5,0,1.5,false,false", "2,EUR,2,0,2,false,false"]; "type case insensitivity")]
+#[test_case("file_with_spaces.csv", ["1,EUR,1.5,0,1.5,false,false", "2,EUR,2,0,2,false,false"]; "file with spaces")]
+#[test_case("precision_up_to_4_decimal.csv", ["1,EUR,2000000000.1235,0,2000000000.1235,false,false"]; "precision up to 4 decimal")]
+#[test_case("invalid_amounts.csv", ["1,EUR,100,0,100,false,false"]; "invalid amounts are rejected")]
+#[test_case("transactions.jsonl", ["1,EUR,1.5,0,1.5,false,false", "2,EUR,3.5,0,3.5,false,false"]; "json lines")]
+#[test_case("unlock.csv", ["1,EUR,5,0,5,false,false"]; "unlock with a reason")]
+#[test_case("currencies.csv", ["1,EUR,0,10,10,false,false", "1,USD,3,0,3,false,false", "3,GBP,1.5,0,1.5,false,false"]; "balances per currency")]
 fn test_file_without_white_spaces<const N: usize>(file_name: &str, expected_lines: [&str; N]) {
+    let result = run(&[input(file_name)], EngineOptions::default()).unwrap();
+    let result_lines: Vec<&str> = result.lines().skip(1).collect(); // Skip header
+
+    assert_eq!(result_lines, expected_lines);
+}
+
+#[test_case(ClientOrder::Id, ["1,EUR,15,5,20,false,false", "2,EUR,30,0,30,false,false", "3,EUR,10,0,10,false,false", "4,EUR,5,0,5,false,false"]; "by id")]
+#[test_case(ClientOrder::Total, ["4,EUR,5,0,5,false,false", "3,EUR,10,0,10,false,false", "1,EUR,15,5,20,false,false", "2,EUR,30,0,30,false,false"]; "by total")]
+#[test_case(ClientOrder::Available, ["4,EUR,5,0,5,false,false", "3,EUR,10,0,10,false,false", "1,EUR,15,5,20,false,false", "2,EUR,30,0,30,false,false"]; "by available")]
+fn test_client_order<const N: usize>(order: ClientOrder, expected_lines: [&str; N]) {
+    let mut engine = TxEngine::default();
+    process_inputs(
+        &mut engine,
+        &[input("client_order.csv")],
+        None,
+        &mut Rejections::default(),
+    )
+    .unwrap();
+
+    let result = output(&engine, order);
+    let result_lines: Vec<&str> = result.lines().skip(1).collect(); // Skip header
+
+    assert_eq!(result_lines, expected_lines);
+}
+
+#[test_case(OutputFormat::Csv, "client,currency,available,held,total,locked,overdrawn\n1,EUR,1.5,0,1.5,false,false\n1,GBP,2,0,2,false,false\n2,EUR,0,20000.1234,20000.1234,false,false\n"; "csv")]
+#[test_case(OutputFormat::Jsonl, concat!(
+    r#"{"client":1,"currency":"EUR","available":"1.5","held":"0","total":"1.5","locked":false,"overdrawn":false}"#, "\n",
+    r#"{"client":1,"currency":"GBP","available":"2","held":"0","total":"2","locked":false,"overdrawn":false}"#, "\n",
+    r#"{"client":2,"currency":"EUR","available":"0","held":"20000.1234","total":"20000.1234","locked":false,"overdrawn":false}"#, "\n",
+); "json lines")]
+#[test_case(OutputFormat::Table, concat!(
+    "client  currency  available        held       total  locked  overdrawn\n",
+    "------  --------  ---------  ----------  ----------  ------  ---------\n",
+    "     1  EUR             1.5           0         1.5  false   false\n",
+    "     1  GBP               2           0           2  false   false\n",
+    "     2  EUR               0  20000.1234  20000.1234  false   false\n",
+); "table")]
+fn test_output_format(format: OutputFormat, expected: &str) {
+    let mut engine = TxEngine::default();
+    for row in [
+        "deposit,1,1,1.50",
+        "deposit,2,2,20000.1234",
+        "dispute,2,2",
+        "deposit,1,3,2,,,gbp",
+    ] {
+        engine
+            .process_tx(TransactionRecord::from_csv_row(row).unwrap())
+            .unwrap();
+    }
+
     let mut buf = Vec::new();
-    process_file(format!("./test_files/{file_name}"), &mut buf).unwrap();
+    write_clients(&engine, ClientOrder::Id, format, &mut buf).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), expected);
+}
+
+#[test]
+fn test_json_output_keeps_amounts_as_strings() {
+    let mut engine = TxEngine::default();
+    engine
+        .process_tx(TransactionRecord::from_csv_row("deposit,1,1,0.0001").unwrap())
+        .unwrap();
 
-    let result = String::from_utf8(buf).expect("Invalid UTF-8");
-    let result_lines: HashSet<&str> = result.lines().skip(1).collect(); // Skip header
+    let mut buf = Vec::new();
+    write_clients(&engine, ClientOrder::Id, OutputFormat::Json, &mut buf).unwrap();
+    let clients: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+    assert_eq!(
+        clients,
+        serde_json::json!([{"client": 1, "currency": "EUR", "available": "0.0001", "held": "0", "total": "0.0001", "locked": false, "overdrawn": false}])
+    );
+}
+
+#[test_case(r#""1.5""#, "1.5"; "string")]
+#[test_case("1.5", "1.5"; "number")]
+#[test_case("42", "42"; "integer")]
+#[test_case("922337203685477.5807", "922337203685477.5807"; "number beyond float precision")]
+fn test_json_record_amount(amount: &str, expected: &str) {
+    let json = format!(r#"{{"type": "deposit", "client": 1, "tx": 1, "amount": {amount}}}"#);
+    let record = TransactionRecord::from_json(json.as_bytes()).unwrap();
+    assert_eq!(record.tx_type.amount().unwrap().get().to_string(), expected);
+}
+
+#[test_case("1e5"; "exponent")]
+#[test_case("1.23456"; "too precise")]
+#[test_case("-1"; "negative")]
+#[test_case("true"; "boolean")]
+fn test_json_record_invalid_amount(amount: &str) {
+    let json = format!(r#"{{"type": "deposit", "client": 1, "tx": 1, "amount": {amount}}}"#);
+    assert!(TransactionRecord::from_json(json.as_bytes()).is_err());
+}
+
+#[test_case(WithdrawalDisputePolicy::Reject, ["1,EUR,50,0,50,false,false", "2,EUR,50,0,50,false,false"]; "reject")]
+#[test_case(WithdrawalDisputePolicy::Reverse, ["1,EUR,50,0,50,false,false", "2,EUR,100,0,100,true,false"]; "reverse")]
+#[test_case(WithdrawalDisputePolicy::Legacy, ["1,EUR,50,0,50,false,false", "2,EUR,0,0,0,true,false"]; "legacy")]
+fn test_withdrawal_dispute_policy<const N: usize>(
+    policy: WithdrawalDisputePolicy,
+    expected_lines: [&str; N],
+) {
+    let options = EngineOptions {
+        withdrawal_dispute_policy: policy,
+        ..Default::default()
+    };
+    let result = run(&[input("withdrawal_disputes.csv")], options).unwrap();
+    let result_lines: Vec<&str> = result.lines().skip(1).collect(); // Skip header
 
-    let expected_lines = HashSet::from(expected_lines);
     assert_eq!(result_lines, expected_lines);
 }
+
+#[test]
+fn test_credit_limits() {
+    let config = Config::try_parse_from([
+        "transactions",
+        "--credit-limits",
+        "./test_files/credit_limits.csv",
+        "./test_files/overdraft.csv",
+    ])
+    .unwrap();
+    let result = run(&config.inputs, config.engine.options().unwrap()).unwrap();
+    let result_lines: Vec<&str> = result.lines().skip(1).collect(); // Skip header
+
+    assert_eq!(
+        result_lines,
+        [
+            "1,EUR,-50,0,-50,false,true",
+            "2,USD,-20,0,-20,false,true",
+            "3,EUR,-5,0,-5,false,true"
+        ]
+    );
+}
+
+#[test]
+fn test_risk_rules() {
+    let config = Config::try_parse_from([
+        "transactions",
+        "--risk-rules",
+        "./test_files/risk_rules.json",
+        "./test_files/risky.csv",
+    ])
+    .unwrap();
+    let result = run(&config.inputs, config.engine.options().unwrap()).unwrap();
+    let result_lines: Vec<&str> = result.lines().skip(1).collect(); // Skip header
+
+    // Client 1 is over the maximum once and over the velocity once, client 2 is locked
+    // by its first dispute, the USD withdrawal of client 3 is not limited by the maximum
+    // and fails for the funds, client 4 is denied
+    assert_eq!(
+        result_lines,
+        [
+            "1,EUR,390,0,390,false,false",
+            "2,EUR,50,50,100,true,false",
+            "3,EUR,20,0,20,false,false"
+        ]
+    );
+}
+
+#[test]
+fn test_invalid_risk_rules_are_reported() {
+    let config = Config::try_parse_from([
+        "transactions",
+        "--risk-rules",
+        "./test_files/rates.csv",
+        "./test_files/risky.csv",
+    ])
+    .unwrap();
+    let error = config.engine.options().unwrap_err();
+
+    assert!(format!("{error:#}").contains("Failed to read risk rules"));
+}
+
+#[test]
+fn test_dispute_window_secs_is_not_sharded() {
+    let args = [
+        "transactions",
+        "--dispute-window-secs",
+        "10",
+        "./test_files/dispute_window_clock.csv",
+    ];
+    let config = Config::try_parse_from(args).unwrap();
+    let result = run(&config.inputs, config.engine.options().unwrap()).unwrap();
+
+    // The untimestamped dispute is as late as the latest record of any client
+    assert_eq!(result.lines().nth(1), Some("1,EUR,10,0,10,false,false"));
+    // A shard would only see the clock of its own clients and accept the dispute
+    assert!(Config::try_parse_from(args.into_iter().chain(["--threads", "2"])).is_err());
+    assert!(Config::try_parse_from([
+        "transactions",
+        "--evict-expired",
+        "--threads",
+        "2",
+        "./test_files/dispute_window_clock.csv",
+    ])
+    .is_err());
+}
+
+#[test]
+fn test_fatal_worker_error_stops_sharded_processing() {
+    let origin = |line| RecordOrigin {
+        source: "input.csv".into(),
+        line: Some(line),
+        raw: String::new(),
+        timestamp: None,
+    };
+    let errors = vec![
+        ((origin(1), 1, 1), ProcessingError::InsufficientFunds),
+        (
+            (origin(2), 1, 2),
+            ProcessingError::Storage("disk full".into()),
+        ),
+        ((origin(3), 1, 3), ProcessingError::InsufficientFunds),
+    ];
+    let mut rejections = Rejections::default();
+
+    let error = report_sharded_errors(errors, &mut rejections).unwrap_err();
+    assert!(error.to_string().contains("disk full"));
+    // The fatal error is not reported as a rejected record, nothing after it is reported
+    assert_eq!(
+        rejections.counts().iter().collect::<Vec<_>>(),
+        [(&"insufficient_funds", &1)]
+    );
+}
+
+#[test]
+fn test_multiple_inputs_feed_one_engine() {
+    let inputs = [input("daily_1.csv"), input("daily_2.csv")];
+    let result = run(&inputs, EngineOptions::default()).unwrap();
+    let result_lines: Vec<&str> = result.lines().skip(1).collect(); // Skip header
+
+    // The second file disputes and charges back a deposit from the first one
+    assert_eq!(
+        result_lines,
+        ["1,EUR,10,0,10,true,false", "2,EUR,5,0,5,false,false"]
+    );
+}
+
+#[test]
+fn test_missing_input_file_is_reported() {
+    let inputs = [input("daily_1.csv"), input("missing.csv")];
+    let error = run(&inputs, EngineOptions::default()).unwrap_err();
+
+    assert!(error.to_string().contains("missing.csv"));
+}
+
+#[test_case("a/transactions.csv", InputFormat::Csv; "csv")]
+#[test_case("transactions.jsonl", InputFormat::Jsonl; "json lines")]
+#[test_case("transactions.ndjson", InputFormat::Jsonl; "newline delimited json")]
+#[test_case("transactions.bin", InputFormat::Binary; "binary")]
+#[test_case("transactions", InputFormat::Csv; "no extension")]
+#[test_case("-", InputFormat::Csv; "stdin")]
+fn test_input_format_detection(input: &str, expected: InputFormat) {
+    let source = InputSource::from(std::ffi::OsStr::new(input));
+    assert_eq!(InputFormat::detect(&source), expected);
+}
+
+#[test]
+fn test_binary_input() {
+    let path = std::env::temp_dir().join("transactions_test_binary_input.bin");
+    let mut encoded: Vec<u8> = [
+        "deposit,1,1,1.0",
+        "Deposit,2,2,2.0",
+        "deposit,1,3,2.0",
+        "withdrawal,1,4,1.5",
+        "withdrawal,2,5,3.0",
+        "deposit,1,6,1.0,,1700000000",
+        "dispute,1,6,,,2023-11-14T22:14:20Z",
+        "deposit,2,7,4.0,,,usd",
+        "deposit,1,8,1.0,,1700000001,gbp",
+        "limit,2,9,3,Overdraft line approved,,usd",
+        "withdrawal,2,10,6.0,,,usd",
+    ]
+    .into_iter()
+    .flat_map(|row| encode_binary(&TransactionRecord::from_csv_row(row).unwrap()))
+    .collect();
+    // Same validation as the textual formats - an unknown type and a negative amount
+    encoded.extend_from_slice(&[9, 0, 0, 1, b'x', 1, 0, 6, 0, 0, 0]);
+    encoded.extend_from_slice(&[
+        24, 0, 1, 7, b'd', b'e', b'p', b'o', b's', b'i', b't', 1, 0, 7, 0, 0, 0,
+    ]);
+    encoded.extend_from_slice(&(-10_000_i64).to_le_bytes());
+    // Record cut off by the end of the input
+    encoded.extend_from_slice(&[24, 0, 1, 7, b'd']);
+    std::fs::write(&path, encoded).unwrap();
+
+    let result = run(&[InputSource::File(path.clone())], EngineOptions::default());
+    std::fs::remove_file(&path).unwrap();
+    let result = result.unwrap();
+    let result_lines: Vec<&str> = result.lines().skip(1).collect(); // Skip header
+
+    assert_eq!(
+        result_lines,
+        [
+            "1,EUR,1.5,1,2.5,false,false",
+            "1,GBP,1,0,1,false,false",
+            "2,EUR,2,0,2,false,false",
+            "2,USD,-2,0,-2,false,true"
+        ]
+    );
+}
+
+#[test]
+fn test_input_format_override() {
+    let mut engine = TxEngine::default();
+    let result = process_inputs(
+        &mut engine,
+        &[input("transactions.jsonl")],
+        Some(InputFormat::Binary),
+        &mut Rejections::default(),
+    );
+
+    // Lines of JSON read as binary records are rejected, nothing is processed
+    assert!(result.is_ok());
+    assert_eq!(engine.get_clients().count(), 0);
+}
+
+#[test]
+fn test_statement() {
+    let mut engine = TxEngine::default();
+    let entries = build_statement(&mut engine, 1, &[input("statement.csv")], None).unwrap();
+
+    let mut buf = Vec::new();
+    write_statement(&entries, &mut buf).unwrap();
+    let statement = String::from_utf8(buf).unwrap();
+    let lines: Vec<&str> = statement
+        .lines()
+        .skip(1) // Skip header
+        .map(|line| line.split_once(',').unwrap().1) // Skip source
+        .collect();
+
+    assert_eq!(
+        lines,
+        [
+            "2,2024-03-01T09:00:00Z,deposit,1,100,EUR,accepted,100,0,100,false,resolved,",
+            "4,2024-03-01T09:00:00Z,withdrawal,3,30,EUR,accepted,70,0,70,false,committed,",
+            "5,2024-03-01T10:00:00Z,withdrawal,4,500,EUR,insufficient_funds,70,0,70,false,,",
+            "6,2024-03-02T08:00:00Z,dispute,1,100,EUR,accepted,-30,100,70,false,resolved,",
+            "8,2024-03-03T08:00:00Z,resolve,1,100,EUR,accepted,70,0,70,false,resolved,",
+            "9,,deposit,5,20,EUR,accepted,90,0,90,false,charged_back,",
+            "10,2024-03-04T08:00:00Z,dispute,5,20,EUR,accepted,70,20,90,false,charged_back,",
+            "11,2024-03-05T08:00:00Z,chargeback,5,20,EUR,accepted,70,0,70,true,charged_back,",
+            "12,2024-03-06T08:00:00Z,deposit,6,1,EUR,client_locked,70,0,70,true,,",
+            "13,2024-03-07T08:00:00Z,unlock,7,,EUR,accepted,70,0,70,false,,Chargeback reviewed",
+        ]
+    );
+}
+
+#[test]
+fn test_statement_stops_on_engine_failure() {
+    let path = std::env::temp_dir().join("transactions_test_statement_failure.journal");
+    let _ = std::fs::remove_file(&path);
+    let mut engine = TxEngine::default();
+    let (journal, _) = Journal::recover(&path, &mut engine).unwrap();
+    engine.attach_journal(journal);
+
+    // A record too large to be journaled stops the engine
+    let record = TransactionRecord::from_csv_row(&format!("limit,2,0,5,{}", "x".repeat(100_000)));
+    assert!(engine.process_tx(record.unwrap()).unwrap_err().is_fatal());
+
+    // Even when all records are of other clients, the failure is not swallowed
+    let error = build_statement(&mut engine, 9, &[input("statement.csv")], None).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert!(format!("{error:#}").contains("Failed to write the journal"));
+}
+
+#[test]
+fn test_statement_starts_with_opening_balance() {
+    let mut engine = TxEngine::default();
+    process_inputs(
+        &mut engine,
+        &[input("daily_1.csv")],
+        None,
+        &mut Rejections::default(),
+    )
+    .unwrap();
+
+    let entries = build_statement(&mut engine, 1, &[input("daily_2.csv")], None).unwrap();
+
+    let opening = &entries[0];
+    assert_eq!((opening.tx_type.as_str(), opening.tx), ("opening", None));
+    assert!(entries[1..].iter().all(|entry| entry.line.is_some()));
+}
+
+#[test]
+fn test_validate_summary() {
+    let mut engine = TxEngine::default();
+    let summary = validate(&mut engine, &[input("rejections.csv")], None).unwrap();
+
+    assert_eq!(summary.records(), 5);
+    assert_eq!(summary.rejected(), 4);
+    let records_by_type: Vec<_> = summary
+        .records_by_type
+        .iter()
+        .map(|(tx_type, count)| (tx_type.as_str(), *count))
+        .collect();
+    assert_eq!(
+        records_by_type,
+        [("deposit", 2), ("dispute", 1), ("withdrawal", 1)]
+    );
+    let errors: Vec<_> = summary.errors.clone().into_iter().collect();
+    assert_eq!(
+        errors,
+        [
+            ("client_id_not_matched", 1),
+            ("duplicate_transaction_id", 1),
+            ("insufficient_funds", 1),
+            ("parse_error", 1)
+        ]
+    );
+    assert_eq!(
+        summary.affected_clients.into_iter().collect::<Vec<_>>(),
+        [1]
+    );
+    assert!(summary.locked_clients.is_empty());
+}
+
+#[test]
+fn test_validate_balance_diff() {
+    let mut engine = TxEngine::default();
+    let summary = validate(&mut engine, &[input("statement.csv")], None).unwrap();
+    let time_range = summary
+        .time_range
+        .map(|(first, last)| (first.to_string(), last.to_string()));
+    assert_eq!(
+        time_range,
+        Some((
+            "2024-03-01T09:00:00Z".to_string(),
+            "2024-03-07T08:00:00Z".to_string()
+        ))
+    );
+
+    let file = std::fs::File::open("./test_files/expected_balances.csv").unwrap();
+    let expected = read_expected_balances(file).unwrap();
+    assert_eq!(diff_balances(&engine, &expected), []);
+
+    let expected = read_expected_balances(
+        "client,available,held,total,locked\n1,70,0,70,true\n3,1,0,1,false\n".as_bytes(),
+    )
+    .unwrap();
+    let diff = |client, field, expected: &str, actual: &str| BalanceDiff {
+        client,
+        currency: Currency::EUR,
+        field,
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    };
+    // Without the currency column the balances are in the default currency
+    assert_eq!(
+        diff_balances(&engine, &expected),
+        [
+            diff(1, "locked", "true", "false"),
+            diff(2, "balance", "missing", "present"),
+            diff(3, "balance", "present", "missing"),
+        ]
+    );
+}
+
+#[test_case(OutOfOrderPolicy::Accept, 0, "1,EUR,4,0,4,false,false", &[]; "accept")]
+#[test_case(OutOfOrderPolicy::Reject, 0, "1,EUR,10,0,10,false,false", &[2, 3, 4]; "reject")]
+#[test_case(OutOfOrderPolicy::Resequence, 1, "1,EUR,3,0,3,false,false", &[4]; "resequence within a short buffer")]
+#[test_case(OutOfOrderPolicy::Resequence, 10, "1,EUR,4,0,4,false,false", &[]; "resequence")]
+fn test_out_of_order(
+    policy: OutOfOrderPolicy,
+    buffer: usize,
+    expected: &str,
+    rejected: &[TransactionId],
+) {
+    let report_path = std::env::temp_dir().join(format!(
+        "transactions_test_out_of_order_{policy:?}_{buffer}.jsonl"
+    ));
+    let mut rejections = Rejections::create(&report_path, RejectionFormat::Jsonl).unwrap();
+    let mut engine = TxEngine::new(EngineOptions {
+        out_of_order_policy: policy,
+        resequence_buffer: buffer,
+        ..Default::default()
+    });
+    process_inputs(
+        &mut engine,
+        &[input("out_of_order.csv")],
+        None,
+        &mut rejections,
+    )
+    .unwrap();
+    rejections.flush().unwrap();
+    drop(rejections);
+
+    let report = std::fs::read_to_string(&report_path).unwrap();
+    std::fs::remove_file(&report_path).unwrap();
+    let entries: Vec<serde_json::Value> = report
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let rejected_txs: Vec<_> = entries
+        .iter()
+        .map(|entry| entry["tx"].as_u64().unwrap() as TransactionId)
+        .collect();
+
+    assert_eq!(
+        output(&engine, ClientOrder::Id).lines().nth(1),
+        Some(expected)
+    );
+    assert_eq!(rejected_txs, rejected);
+    if policy == OutOfOrderPolicy::Reject {
+        assert_eq!(entries[0]["code"], "out_of_order");
+        assert_eq!(entries[0]["timestamp"], "2024-03-01T09:00:00Z");
+    }
+}
+
+#[test_case(Rounding::HalfEven, "2.7704", "12.7704"; "half even")]
+#[test_case(Rounding::HalfUp, "2.7704", "12.7704"; "half up")]
+#[test_case(Rounding::Down, "2.7703", "12.7703"; "down")]
+fn test_conversion_report(rounding: Rounding, converted_usd: &str, client_sum: &str) {
+    let mut engine = TxEngine::default();
+    process_inputs(
+        &mut engine,
+        &[input("currencies.csv")],
+        None,
+        &mut Rejections::default(),
+    )
+    .unwrap();
+    let rates = std::fs::File::open("./test_files/rates.csv").unwrap();
+    let rates = read_rates(rates, Currency::EUR).unwrap();
+
+    let rows = convert_balances(&engine, &rates, rounding).unwrap();
+    let mut buf = Vec::new();
+    write_conversion(&rows, &mut buf).unwrap();
+
+    let expected = [
+        "client,currency,total,rate,converted_total".to_string(),
+        "1,EUR,10,1,10".to_string(),
+        format!("1,USD,3,0.92345,{converted_usd}"),
+        format!("1,,,,{client_sum}"),
+        "3,GBP,1.5,1.16667,1.75".to_string(),
+        "3,,,,1.75".to_string(),
+    ];
+    assert_eq!(
+        String::from_utf8(buf).unwrap().lines().collect::<Vec<_>>(),
+        expected
+    );
+}
+
+#[test]
+fn test_conversion_without_rate_fails() {
+    let mut engine = TxEngine::default();
+    process_inputs(
+        &mut engine,
+        &[input("currencies.csv")],
+        None,
+        &mut Rejections::default(),
+    )
+    .unwrap();
+    let rates = read_rates("currency,rate\nusd,0.9\n".as_bytes(), Currency::EUR).unwrap();
+
+    assert_eq!(
+        convert_balances(&engine, &rates, Rounding::default()).unwrap_err(),
+        ConversionError::MissingRate(Currency::GBP)
+    );
+}
+
+#[test]
+fn test_rejection_report() {
+    let report_path = std::env::temp_dir().join("transactions_test_rejection_report.jsonl");
+    let mut rejections = Rejections::create(&report_path, RejectionFormat::Jsonl).unwrap();
+    let mut engine = TxEngine::default();
+    process_inputs(
+        &mut engine,
+        &[input("rejections.csv")],
+        None,
+        &mut rejections,
+    )
+    .unwrap();
+    rejections.flush().unwrap();
+    drop(rejections);
+
+    let report = std::fs::read_to_string(&report_path).unwrap();
+    std::fs::remove_file(&report_path).unwrap();
+    let entries: Vec<serde_json::Value> = report
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry["line"].as_u64().unwrap(),
+                entry["raw"].as_str().unwrap(),
+                entry["client"].as_u64(),
+                entry["tx"].as_u64(),
+                entry["code"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (
+                3,
+                "withdrawal,1,2,50",
+                Some(1),
+                Some(2),
+                "insufficient_funds"
+            ),
+            (4, "deposit,x,3,10", None, Some(3), "parse_error"),
+            (5, "dispute,2,1,", Some(2), Some(1), "client_id_not_matched"),
+            (
+                6,
+                "deposit,1,1,5",
+                Some(1),
+                Some(1),
+                "duplicate_transaction_id"
+            ),
+        ]
+    );
+    assert!(entries
+        .iter()
+        .all(|entry| entry["source"] == "./test_files/rejections.csv"));
+}
+
+mod server {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{SocketAddr, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use serde_json::{json, Value};
+
+    use crate::{
+        engine::TxEngine,
+        server::{Server, MAX_LINE_LENGTH},
+    };
+
+    fn start_server() -> SocketAddr {
+        start_server_with_max_connections(16)
+    }
+
+    fn start_server_with_max_connections(max_connections: u16) -> SocketAddr {
+        let server = Server::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            TxEngine::default(),
+            max_connections,
+        )
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    struct Connection {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Connection {
+        fn open(addr: SocketAddr) -> Self {
+            let writer = TcpStream::connect(addr).unwrap();
+            let reader = BufReader::new(writer.try_clone().unwrap());
+            Self { reader, writer }
+        }
+
+        fn request(&mut self, line: &str) -> Value {
+            writeln!(self.writer, "{line}").unwrap();
+            let mut response = String::new();
+            self.reader.read_line(&mut response).unwrap();
+            serde_json::from_str(&response).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_records_are_acknowledged() {
+        let mut connection = Connection::open(start_server());
+
+        assert_eq!(
+            connection.request("deposit, 1, 1, 10.5"),
+            json!({"status": "ok"})
+        );
+        assert_eq!(
+            connection.request(r#"{"type": "Withdrawal", "client": 1, "tx": 2, "amount": "0.5"}"#),
+            json!({"status": "ok"})
+        );
+        assert_eq!(connection.request("dispute,1,1"), json!({"status": "ok"}));
+        assert_eq!(
+            connection.request("balance 1"),
+            json!({"status": "ok", "client": {
+                "client": 1,
+                "balances": {"EUR": {"available": "-0.5", "held": "10.5", "total": "10"}},
+                "locked": false
+            }})
+        );
+    }
+
+    #[test]
+    fn test_errors_are_typed() {
+        let mut connection = Connection::open(start_server());
+
+        connection.request("deposit,1,1,1");
+        let cases = [
+            ("withdrawal,1,2,5", "insufficient_funds"),
+            ("deposit,1,1,1", "duplicate_transaction_id"),
+            ("resolve,1,1", "not_under_dispute"),
+            ("deposit,1,3,-1", "parse_error"),
+            ("{\"type\": \"deposit\"", "parse_error"),
+            ("balance 2", "client_not_found"),
+        ];
+        for (line, code) in cases {
+            let response = connection.request(line);
+            assert_eq!(response["status"], "error", "{line}");
+            assert_eq!(response["code"], code, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_concurrent_connections_share_engine() {
+        let addr = start_server();
+
+        let handles: Vec<_> = (0..4u32)
+            .map(|n| {
+                thread::spawn(move || {
+                    let mut connection = Connection::open(addr);
+                    for i in 0..50 {
+                        let response = connection.request(&format!("deposit,1,{},1", n * 50 + i));
+                        assert_eq!(response, json!({"status": "ok"}));
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        let balance = Connection::open(addr).request("balance 1");
+        assert_eq!(balance["client"]["balances"]["EUR"]["total"], "200");
+    }
+
+    #[test]
+    fn test_line_length_is_capped() {
+        let addr = start_server();
+        let mut connection = Connection::open(addr);
+
+        let record = "deposit,1,1,1";
+        let longest = record.to_string() + &" ".repeat(MAX_LINE_LENGTH - record.len());
+        assert_eq!(connection.request(&longest), json!({"status": "ok"}));
+
+        connection
+            .writer
+            .write_all(&[b'x'; MAX_LINE_LENGTH + 1])
+            .unwrap();
+        let mut response = String::new();
+        connection.reader.read_line(&mut response).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["code"], "line_too_long");
+        // The connection is closed
+        assert_eq!(connection.reader.read_line(&mut String::new()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_idle_connections_do_not_block_others() {
+        let addr = start_server_with_max_connections(3);
+        let _idle = [Connection::open(addr), Connection::open(addr)];
+
+        let mut active = Connection::open(addr);
+        assert_eq!(active.request("deposit,1,1,1"), json!({"status": "ok"}));
+    }
+
+    #[test]
+    fn test_connections_over_the_maximum_are_refused() {
+        let addr = start_server_with_max_connections(1);
+        let mut first = Connection::open(addr);
+        first.request("deposit,1,1,1");
+
+        let mut refused = Connection::open(addr);
+        let mut response = String::new();
+        refused.reader.read_line(&mut response).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["code"], "too_many_connections");
+        // The refused connection is closed
+        assert_eq!(refused.reader.read_line(&mut String::new()).unwrap(), 0);
+
+        // The slot is released once the first connection is closed
+        drop(first);
+        // A refused connection may be reset before its request is read
+        let balance = || -> Option<Value> {
+            let mut connection = Connection::open(addr);
+            writeln!(connection.writer, "balance 1").ok()?;
+            let mut response = String::new();
+            connection.reader.read_line(&mut response).ok()?;
+            let response: Value = serde_json::from_str(&response).ok()?;
+            (response["status"] == "ok").then_some(response)
+        };
+        let balance = (0..50)
+            .find_map(|_| {
+                balance().or_else(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    None
+                })
+            })
+            .unwrap();
+        assert_eq!(balance["client"]["balances"]["EUR"]["total"], "1");
+    }
+}
+
+mod http {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+        thread,
+    };
+
+    use serde_json::{json, Value};
+
+    use crate::{
+        engine::TxEngine,
+        http::{HttpServer, MAX_BODY_SIZE},
+    };
+
+    fn start_server() -> SocketAddr {
+        let server =
+            HttpServer::bind("127.0.0.1:0".parse().unwrap(), TxEngine::default(), 4).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    /// Sends a single request and returns the status code and the parsed body
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn post(addr: SocketAddr, record: Value) -> (u16, Value) {
+        request(addr, "POST", "/transactions", &record.to_string())
+    }
+
+    #[test]
+    fn test_transactions_and_balances() {
+        let addr = start_server();
+
+        let deposit = json!({"type": "deposit", "client": 1, "tx": 1, "amount": "10"});
+        assert_eq!(
+            post(addr, deposit),
+            (
+                200,
+                json!({
+                    "client": 1,
+                    "balances": {"EUR": {"available": "10", "held": "0", "total": "10"}},
+                    "locked": false
+                })
+            )
+        );
+        post(
+            addr,
+            json!({"type": "deposit", "client": 2, "tx": 2, "amount": "1.5"}),
+        );
+        post(addr, json!({"type": "dispute", "client": 1, "tx": 1}));
+
+        let (status, transaction) = request(addr, "GET", "/transactions/1", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            transaction,
+            json!({
+                "id": 1,
+                "kind": "deposit",
+                "amount": "10",
+                "currency": "EUR",
+                "client": 1,
+                "state": "disputed",
+                "sequence": 0,
+                "timestamp": null
+            })
+        );
+
+        let (status, client) = request(addr, "GET", "/clients/1", "");
+        assert_eq!(status, 200);
+        assert_eq!(client["balances"]["EUR"]["held"], "10");
+
+        let (status, clients) = request(addr, "GET", "/clients", "");
+        assert_eq!(status, 200);
+        assert_eq!(clients[0]["client"], 1);
+        assert_eq!(clients[1]["client"], 2);
+
+        post(
+            addr,
+            json!({"type": "deposit", "client": 1, "tx": 3, "amount": "1"}),
+        );
+        let (status, history) = request(addr, "GET", "/clients/1/transactions", "");
+        assert_eq!(status, 200);
+        assert_eq!(history[0]["state"], "disputed");
+        assert_eq!(history[1]["id"], 3);
+    }
+
+    #[test]
+    fn test_error_status_codes() {
+        let addr = start_server();
+
+        post(
+            addr,
+            json!({"type": "deposit", "client": 1, "tx": 1, "amount": "10"}),
+        );
+        post(addr, json!({"type": "dispute", "client": 1, "tx": 1}));
+
+        let cases = [
+            (
+                json!({"type": "dispute", "client": 1, "tx": 1}),
+                409,
+                "cannot_be_disputed",
+            ),
+            (
+                json!({"type": "resolve", "client": 1, "tx": 7}),
+                404,
+                "referred_tx_not_found",
+            ),
+            (
+                json!({"type": "withdrawal", "client": 1, "tx": 2, "amount": "5"}),
+                422,
+                "insufficient_funds",
+            ),
+            (
+                json!({"type": "deposit", "client": 1, "tx": 3, "amount": "-5"}),
+                400,
+                "parse_error",
+            ),
+        ];
+        for (record, status, code) in cases {
+            let (actual_status, body) = post(addr, record.clone());
+            assert_eq!(
+                (actual_status, &body["code"]),
+                (status, &json!(code)),
+                "{record}"
+            );
+        }
+
+        post(addr, json!({"type": "chargeback", "client": 1, "tx": 1}));
+        let (status, body) = post(
+            addr,
+            json!({"type": "deposit", "client": 1, "tx": 4, "amount": "1"}),
+        );
+        assert_eq!((status, &body["code"]), (423, &json!("client_locked")));
+
+        assert_eq!(request(addr, "GET", "/clients/9", "").0, 404);
+        assert_eq!(request(addr, "GET", "/transactions/abc", "").0, 400);
+        assert_eq!(request(addr, "DELETE", "/clients/1", "").0, 405);
+        assert_eq!(request(addr, "GET", "/unknown", "").0, 404);
+    }
+
+    #[test]
+    fn test_body_size_is_capped() {
+        let addr = start_server();
+
+        let reason = "x".repeat(MAX_BODY_SIZE);
+        let record = json!({"type": "unlock", "client": 1, "tx": 1, "reason": reason});
+        let (status, body) = post(addr, record);
+        assert_eq!((status, &body["code"]), (413, &json!("payload_too_large")));
+
+        // The server keeps serving after rejecting the body
+        let deposit = json!({"type": "deposit", "client": 1, "tx": 1, "amount": 1});
+        assert_eq!(post(addr, deposit).0, 200);
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use chrono::{DateTime, SecondsFormat};
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::errors::TimestampError;
+
+/// Point in time with a precision of seconds, kept as the number of seconds since the Unix epoch.
+/// It is read from RFC 3339 or from the number of seconds, and always written in RFC 3339 (UTC).
+/// Fractions of a second are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub fn from_secs(secs: i64) -> Self {
+        Timestamp(secs)
+    }
+
+    /// Number of seconds since the Unix epoch
+    pub fn secs(self) -> i64 {
+        self.0
+    }
+
+    /// Number of seconds since the earlier timestamp, negative if it is later
+    pub fn secs_since(self, earlier: Timestamp) -> i64 {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = TimestampError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(secs) = s.parse::<i64>() {
+            return Ok(Timestamp(secs));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|time| Timestamp(time.timestamp()))
+            .map_err(|_| TimestampError::Invalid(s.to_string()))
+    }
+}
+
+// Timestamps out of the range of the calendar are written as the number of seconds
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match DateTime::from_timestamp(self.0, 0) {
+            Some(time) => f.write_str(&time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Accepts both forms, as a string or as a number of seconds in formats with typed numbers
+struct TimestampVisitor;
+
+impl Visitor<'_> for TimestampVisitor {
+    type Value = Timestamp;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an RFC 3339 timestamp or a number of seconds since the Unix epoch")
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Timestamp(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        i64::try_from(value)
+            .map(Timestamp)
+            .map_err(|_| de::Error::custom(TimestampError::Invalid(value.to_string())))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("1700000000", 1_700_000_000; "epoch seconds")]
+    #[test_case("-60", -60; "before the epoch")]
+    #[test_case("2023-11-14T22:13:20Z", 1_700_000_000; "utc")]
+    #[test_case("2023-11-14T23:13:20+01:00", 1_700_000_000; "offset")]
+    #[test_case("2023-11-14T22:13:20.999Z", 1_700_000_000; "fraction is dropped")]
+    fn test_parse(input: &str, expected: i64) {
+        assert_eq!(input.parse::<Timestamp>().unwrap(), Timestamp(expected));
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("2023-11-14"; "date only")]
+    #[test_case("1.5"; "fractional seconds")]
+    fn test_parse_error(input: &str) {
+        assert_eq!(
+            input.parse::<Timestamp>().unwrap_err(),
+            TimestampError::Invalid(input.to_string())
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Timestamp(1_700_000_000).to_string(), "2023-11-14T22:13:20Z");
+    }
+}
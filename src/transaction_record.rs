@@ -1,35 +1,145 @@
 use std::fmt::{self, Debug, Display};
 
+use csv::{ReaderBuilder, StringRecord, Trim};
 use serde::{
     de::{self, MapAccess, Visitor},
-    Deserialize, Deserializer,
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::{ClientId, Float, TransactionId};
+use crate::{
+    amount::PositiveAmount, currency::Currency, errors::RecordError, timestamp::Timestamp,
+    ClientId, TransactionId,
+};
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub enum TransactionRecordType {
-    Deposit { amount: Float },
-    Withdrawal { amount: Float },
+    Deposit {
+        amount: PositiveAmount,
+    },
+    Withdrawal {
+        amount: PositiveAmount,
+    },
     Dispute,
     Resolve,
     Chargeback,
+    /// Admin record lifting the lock of an account, the reason is kept for the audit trail
+    Unlock {
+        reason: String,
+    },
+    /// Admin record setting the credit limit of the client in the currency of the record,
+    /// without a limit the credit line is revoked
+    Limit {
+        limit: Option<PositiveAmount>,
+        reason: String,
+    },
+}
+
+impl TransactionRecordType {
+    pub fn amount(&self) -> Option<PositiveAmount> {
+        match self {
+            TransactionRecordType::Deposit { amount }
+            | TransactionRecordType::Withdrawal { amount } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            TransactionRecordType::Unlock { reason }
+            | TransactionRecordType::Limit { reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Credit limit set by a limit record
+    pub fn limit(&self) -> Option<PositiveAmount> {
+        match self {
+            TransactionRecordType::Limit { limit, .. } => *limit,
+            _ => None,
+        }
+    }
+
+    /// Admin records change the client only, their transaction id is not used
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionRecordType::Unlock { .. } | TransactionRecordType::Limit { .. }
+        )
+    }
 }
 
-impl<'de> Deserialize<'de> for TransactionRecordType {
+// Custom implementation used to avoid exposing amount
+impl Display for TransactionRecordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionRecordType::Deposit { .. } => write!(f, "deposit"),
+            TransactionRecordType::Withdrawal { .. } => write!(f, "withdrawal"),
+            TransactionRecordType::Dispute => write!(f, "dispute"),
+            TransactionRecordType::Resolve => write!(f, "resolve"),
+            TransactionRecordType::Chargeback => write!(f, "chargeback"),
+            TransactionRecordType::Unlock { .. } => write!(f, "unlock"),
+            TransactionRecordType::Limit { .. } => write!(f, "limit"),
+        }
+    }
+}
+
+impl Debug for TransactionRecordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Optional fields of a record, which are required only by some of the record types
+#[derive(Debug, Default)]
+pub struct RecordFields {
+    pub amount: Option<PositiveAmount>,
+    pub reason: Option<String>,
+    pub timestamp: Option<Timestamp>,
+    pub currency: Option<Currency>,
+}
+
+/// This reflects the structure of the transaction records in the input CSV file - not used in the engine
+#[derive(Clone)]
+pub struct TransactionRecord {
+    pub tx_type: TransactionRecordType,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    /// Time of the event
+    pub timestamp: Option<Timestamp>,
+    /// Currency of the amount, or of the referred transaction. The engine
+    /// uses its default currency when it's not given.
+    pub currency: Option<Currency>,
+}
+
+impl<'de> Deserialize<'de> for TransactionRecord {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        // Custom visitor to handle case-insensitive transaction type deserialization
-        struct TransactionTypeVisitor;
+        const FIELDS: &[&str] = &[
+            "type",
+            "client",
+            "tx",
+            "amount",
+            "reason",
+            "timestamp",
+            "currency",
+        ];
+
+        // Custom visitor collecting the fields, the record is built by `TransactionRecord::new`.
+        // Fields are requested with their concrete types, so the amount is always read
+        // from its textual form and parsed into a fixed-point value without precision loss.
+        // Amounts of deposits and withdrawals are validated here, a record with
+        // a negative, zero or non-numeric amount is rejected as a parse error.
+        struct TransactionRecordVisitor;
 
-        impl<'de> Visitor<'de> for TransactionTypeVisitor {
-            type Value = TransactionRecordType;
+        impl<'de> Visitor<'de> for TransactionRecordVisitor {
+            type Value = TransactionRecord;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a transaction type object with a case-insensitive type field")
+                formatter.write_str("a transaction record with a case-insensitive type field")
             }
 
             fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
@@ -37,7 +147,12 @@ impl<'de> Deserialize<'de> for TransactionRecordType {
                 A: MapAccess<'de>,
             {
                 let mut transaction_type: Option<String> = None;
-                let mut amount: Option<Float> = None;
+                let mut client: Option<ClientId> = None;
+                let mut tx: Option<TransactionId> = None;
+                let mut amount: Option<Option<PositiveAmount>> = None;
+                let mut reason: Option<Option<String>> = None;
+                let mut timestamp: Option<Option<Timestamp>> = None;
+                let mut currency: Option<Option<Currency>> = None;
 
                 while let Some(key) = map.next_key::<String>()? {
                     match key.as_str() {
@@ -45,8 +160,19 @@ impl<'de> Deserialize<'de> for TransactionRecordType {
                             if transaction_type.is_some() {
                                 return Err(de::Error::duplicate_field("type"));
                             }
-                            let value: String = map.next_value()?;
-                            transaction_type = Some(value.to_lowercase()); // Case-insensitive
+                            transaction_type = Some(map.next_value()?);
+                        }
+                        "client" => {
+                            if client.is_some() {
+                                return Err(de::Error::duplicate_field("client"));
+                            }
+                            client = Some(map.next_value()?);
+                        }
+                        "tx" => {
+                            if tx.is_some() {
+                                return Err(de::Error::duplicate_field("tx"));
+                            }
+                            tx = Some(map.next_value()?);
                         }
                         "amount" => {
                             if amount.is_some() {
@@ -54,62 +180,162 @@ impl<'de> Deserialize<'de> for TransactionRecordType {
                             }
                             amount = Some(map.next_value()?);
                         }
-                        _ => return Err(de::Error::unknown_field(&key, &["type", "amount"])),
+                        "reason" => {
+                            if reason.is_some() {
+                                return Err(de::Error::duplicate_field("reason"));
+                            }
+                            reason = Some(map.next_value()?);
+                        }
+                        "timestamp" => {
+                            if timestamp.is_some() {
+                                return Err(de::Error::duplicate_field("timestamp"));
+                            }
+                            timestamp = Some(map.next_value()?);
+                        }
+                        "currency" => {
+                            if currency.is_some() {
+                                return Err(de::Error::duplicate_field("currency"));
+                            }
+                            currency = Some(map.next_value()?);
+                        }
+                        _ => return Err(de::Error::unknown_field(&key, FIELDS)),
                     }
                 }
 
                 let transaction_type =
                     transaction_type.ok_or_else(|| de::Error::missing_field("type"))?;
-
-                match transaction_type.as_str() {
-                    "deposit" => {
-                        let amount = amount.ok_or_else(|| de::Error::missing_field("amount"))?;
-                        Ok(TransactionRecordType::Deposit { amount })
-                    }
-                    "withdrawal" => {
-                        let amount = amount.ok_or_else(|| de::Error::missing_field("amount"))?;
-                        Ok(TransactionRecordType::Withdrawal { amount })
+                let client = client.ok_or_else(|| de::Error::missing_field("client"))?;
+                let tx = tx.ok_or_else(|| de::Error::missing_field("tx"))?;
+                let fields = RecordFields {
+                    amount: amount.flatten(),
+                    reason: reason.flatten(),
+                    timestamp: timestamp.flatten(),
+                    currency: currency.flatten(),
+                };
+                TransactionRecord::new(&transaction_type, client, tx, fields).map_err(|e| match e {
+                    RecordError::UnknownType(tx_type) => {
+                        de::Error::unknown_variant(&tx_type, TransactionRecord::TYPES)
                     }
-                    "dispute" => Ok(TransactionRecordType::Dispute),
-                    "resolve" => Ok(TransactionRecordType::Resolve),
-                    "chargeback" => Ok(TransactionRecordType::Chargeback),
-                    _ => Err(de::Error::unknown_variant(
-                        &transaction_type,
-                        &["deposit", "withdrawal", "dispute", "resolve", "chargeback"],
-                    )),
-                }
+                    RecordError::MissingAmount(_) => de::Error::missing_field("amount"),
+                    RecordError::MissingReason(_) => de::Error::missing_field("reason"),
+                })
             }
         }
 
-        // Deserialize the struct using the custom visitor
-        deserializer.deserialize_any(TransactionTypeVisitor)
+        deserializer.deserialize_struct("TransactionRecord", FIELDS, TransactionRecordVisitor)
     }
 }
 
-// Custom implementation used to avoid exposing amount
-impl Display for TransactionRecordType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            TransactionRecordType::Deposit { .. } => write!(f, "deposit"),
-            TransactionRecordType::Withdrawal { .. } => write!(f, "withdrawal"),
-            TransactionRecordType::Dispute => write!(f, "dispute"),
-            TransactionRecordType::Resolve => write!(f, "resolve"),
-            TransactionRecordType::Chargeback => write!(f, "chargeback"),
-        }
+impl TransactionRecord {
+    const TYPES: &'static [&'static str] = &[
+        "deposit",
+        "withdrawal",
+        "dispute",
+        "resolve",
+        "chargeback",
+        "unlock",
+        "limit",
+    ];
+
+    /// Builds a record from its fields, the type is case-insensitive, the amount
+    /// is required for deposits and withdrawals and a non-empty reason for admin records.
+    /// Used by all input formats, so the same records are accepted regardless of the encoding.
+    pub fn new(
+        tx_type: &str,
+        client: ClientId,
+        tx: TransactionId,
+        fields: RecordFields,
+    ) -> Result<Self, RecordError> {
+        let RecordFields {
+            amount,
+            reason,
+            timestamp,
+            currency,
+        } = fields;
+        let reason = |tx_type| {
+            reason
+                .filter(|reason| !reason.trim().is_empty())
+                .ok_or(RecordError::MissingReason(tx_type))
+        };
+        let tx_type = match tx_type.to_lowercase().as_str() {
+            "deposit" => TransactionRecordType::Deposit {
+                amount: amount.ok_or(RecordError::MissingAmount("deposit"))?,
+            },
+            "withdrawal" => TransactionRecordType::Withdrawal {
+                amount: amount.ok_or(RecordError::MissingAmount("withdrawal"))?,
+            },
+            "dispute" => TransactionRecordType::Dispute,
+            "resolve" => TransactionRecordType::Resolve,
+            "chargeback" => TransactionRecordType::Chargeback,
+            "unlock" => TransactionRecordType::Unlock {
+                reason: reason("unlock")?,
+            },
+            "limit" => TransactionRecordType::Limit {
+                limit: amount,
+                reason: reason("limit")?,
+            },
+            _ => return Err(RecordError::UnknownType(tx_type.to_string())),
+        };
+        Ok(Self {
+            tx_type,
+            client,
+            tx,
+            timestamp,
+            currency,
+        })
     }
-}
 
-impl Debug for TransactionRecordType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
+    /// Parses a single headerless CSV row with the
+    /// `type,client,tx,amount,reason,timestamp,currency` columns,
+    /// the trailing optional columns may be omitted
+    pub fn from_csv_row(row: &str) -> Result<Self, csv::Error> {
+        let headers = StringRecord::from(vec![
+            "type",
+            "client",
+            "tx",
+            "amount",
+            "reason",
+            "timestamp",
+            "currency",
+        ]);
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(row.as_bytes());
+        let mut record = StringRecord::new();
+        reader.read_record(&mut record)?;
+        record.deserialize(Some(&headers))
+    }
+
+    /// Parses a record given as a JSON object. The amount may be a JSON number as well as
+    /// a string - numbers keep their exact text (serde_json's `arbitrary_precision`),
+    /// so they are parsed the same way as strings, never through a binary float.
+    pub fn from_json(json: &[u8]) -> Result<Self, serde_json::Error> {
+        let mut value: serde_json::Value = serde_json::from_slice(json)?;
+        if let Some(amount) = value.get_mut("amount") {
+            if let serde_json::Value::Number(number) = amount {
+                *amount = serde_json::Value::String(number.to_string());
+            }
+        }
+        Self::deserialize(value)
     }
 }
 
-/// This reflects the structure of the transaction records in the input CSV file - not used in the engine
-#[derive(Deserialize, Clone)]
-pub struct TransactionRecord {
-    #[serde(flatten)]
-    pub tx_type: TransactionRecordType,
-    pub client: ClientId,
-    pub tx: TransactionId,
+// Mirrors the input structure, so a serialized record can be read back by the deserializer
+impl Serialize for TransactionRecord {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut record = serializer.serialize_struct("TransactionRecord", 7)?;
+        record.serialize_field("type", &self.tx_type.to_string())?;
+        record.serialize_field("client", &self.client)?;
+        record.serialize_field("tx", &self.tx)?;
+        record.serialize_field("amount", &self.tx_type.amount().or(self.tx_type.limit()))?;
+        record.serialize_field("reason", &self.tx_type.reason())?;
+        record.serialize_field("timestamp", &self.timestamp)?;
+        record.serialize_field("currency", &self.currency)?;
+        record.end()
+    }
 }
@@ -0,0 +1,254 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    amount::Amount,
+    currency::Currency,
+    engine::{ClientOrder, TxEngine},
+    input::{InputFormat, InputSource},
+    read_input,
+    rejections::{Rejection, Rejections, PARSE_ERROR_CODE},
+    timestamp::Timestamp,
+    ClientId,
+};
+
+/// Result of a dry run of the inputs
+#[derive(Debug, Default)]
+pub struct ValidationSummary {
+    /// Number of the parsed records by their type
+    pub records_by_type: BTreeMap<String, u64>,
+    /// Number of the rejected records by their error code, including records
+    /// that could not be parsed
+    pub errors: BTreeMap<&'static str, u64>,
+    /// Clients with at least one accepted record
+    pub affected_clients: BTreeSet<ClientId>,
+    /// Earliest and latest timestamp of the parsed records
+    pub time_range: Option<(Timestamp, Timestamp)>,
+    /// Clients locked once all records are processed
+    pub locked_clients: Vec<ClientId>,
+    /// Differences against the expected balances, if they were given
+    pub balance_diffs: Option<Vec<BalanceDiff>>,
+}
+
+impl ValidationSummary {
+    pub fn records(&self) -> u64 {
+        let unparsed = self
+            .errors
+            .get(PARSE_ERROR_CODE)
+            .copied()
+            .unwrap_or_default();
+        self.records_by_type.values().sum::<u64>() + unparsed
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+/// Balances of a client as expected after the processing, in the format of the CSV output.
+/// Without a currency, the balances are in the default currency of the engine.
+#[derive(Debug, Deserialize)]
+pub struct ExpectedBalance {
+    pub client: ClientId,
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// Single field of a client balance that differs from the expected balances. A balance
+/// missing on one of the sides is reported with the `balance` field.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BalanceDiff {
+    pub client: ClientId,
+    pub currency: Currency,
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl BalanceDiff {
+    fn new(
+        (client, currency): (ClientId, Currency),
+        field: &'static str,
+        expected: impl Display,
+        actual: impl Display,
+    ) -> Self {
+        Self {
+            client,
+            currency,
+            field,
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        }
+    }
+}
+
+/// Processes the inputs by the engine and summarizes the outcome. Nothing is persisted -
+/// the engine is expected to have no journal attached, rejections are only printed to stderr.
+pub fn validate(
+    engine: &mut TxEngine,
+    inputs: &[InputSource],
+    format: Option<InputFormat>,
+) -> anyhow::Result<ValidationSummary> {
+    let mut summary = ValidationSummary::default();
+    let mut rejections = Rejections::default();
+    let resequence = engine.options().resequence_buffer();
+    for input in inputs {
+        read_input(
+            input,
+            format,
+            resequence,
+            &mut rejections,
+            |tx, origin, rejections| {
+                *summary
+                    .records_by_type
+                    .entry(tx.tx_type.to_string())
+                    .or_default() += 1;
+                if let Some(time) = tx.timestamp {
+                    summary.time_range = Some(match summary.time_range {
+                        Some((first, last)) => (first.min(time), last.max(time)),
+                        None => (time, time),
+                    });
+                }
+                let (client, tx_id) = (tx.client, tx.tx);
+                match engine.process_tx(tx) {
+                    Ok(()) => {
+                        summary.affected_clients.insert(client);
+                        Ok(())
+                    }
+                    Err(e) if e.is_fatal() => Err(e.into()),
+                    Err(e) => rejections.report(Rejection::processing(origin(), client, tx_id, &e)),
+                }
+            },
+        )?;
+    }
+
+    engine.check_ledger().context("Ledger invariant violated")?;
+    summary.errors = rejections.counts().clone();
+    summary.locked_clients = engine
+        .get_clients()
+        .filter(|client| client.is_locked())
+        .map(|client| client.id())
+        .collect();
+    Ok(summary)
+}
+
+/// Reads the expected balances from a CSV with a `client,currency,available,held,total,locked`
+/// header, the currency column is optional
+pub fn read_expected_balances(reader: impl Read) -> anyhow::Result<Vec<ExpectedBalance>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    Ok(reader.deserialize().collect::<Result<_, _>>()?)
+}
+
+/// Compares the balances of all clients of the engine with the expected ones,
+/// ordered by the client id and the currency
+pub fn diff_balances(engine: &TxEngine, expected: &[ExpectedBalance]) -> Vec<BalanceDiff> {
+    let default_currency = engine.options().currencies.default;
+    let expected: BTreeMap<_, _> = expected
+        .iter()
+        .map(|b| ((b.client, b.currency.unwrap_or(default_currency)), b))
+        .collect();
+    let actual: BTreeMap<_, _> = engine
+        .get_balances(ClientOrder::Id)
+        .map(|b| ((b.id(), b.currency()), b))
+        .collect();
+    let keys: BTreeSet<_> = expected.keys().chain(actual.keys()).copied().collect();
+
+    let mut diffs = Vec::new();
+    for key in keys {
+        let (expected, actual) = match (expected.get(&key), actual.get(&key)) {
+            (Some(expected), Some(actual)) => (expected, actual),
+            (Some(_), None) => {
+                diffs.push(BalanceDiff::new(key, "balance", "present", "missing"));
+                continue;
+            }
+            (None, _) => {
+                diffs.push(BalanceDiff::new(key, "balance", "missing", "present"));
+                continue;
+            }
+        };
+        let amounts = [
+            ("available", expected.available, actual.available()),
+            ("held", expected.held, actual.held()),
+            ("total", expected.total, actual.total()),
+        ];
+        for (field, expected, actual) in amounts {
+            if expected != actual {
+                diffs.push(BalanceDiff::new(key, field, expected, actual));
+            }
+        }
+        if expected.locked != actual.is_locked() {
+            diffs.push(BalanceDiff::new(
+                key,
+                "locked",
+                expected.locked,
+                actual.is_locked(),
+            ));
+        }
+    }
+    diffs
+}
+
+fn join(values: impl IntoIterator<Item = impl Display>) -> String {
+    let values: Vec<_> = values.into_iter().map(|v| v.to_string()).collect();
+    if values.is_empty() {
+        "none".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+/// Writes the summary in a human-readable form
+pub fn write_summary(summary: &ValidationSummary, mut writer: impl Write) -> anyhow::Result<()> {
+    writeln!(
+        writer,
+        "records: {} ({} accepted, {} rejected)",
+        summary.records(),
+        summary.records() - summary.rejected(),
+        summary.rejected()
+    )?;
+    writeln!(writer, "records by type:")?;
+    for (tx_type, count) in &summary.records_by_type {
+        writeln!(writer, "  {tx_type}: {count}")?;
+    }
+    if let Some((first, last)) = summary.time_range {
+        writeln!(writer, "time range: {first} - {last}")?;
+    }
+    writeln!(writer, "errors:")?;
+    for (code, count) in &summary.errors {
+        writeln!(writer, "  {code}: {count}")?;
+    }
+    writeln!(
+        writer,
+        "affected clients: {}",
+        join(&summary.affected_clients)
+    )?;
+    writeln!(writer, "locked clients: {}", join(&summary.locked_clients))?;
+    match &summary.balance_diffs {
+        Some(diffs) if diffs.is_empty() => writeln!(writer, "balances match the expected ones")?,
+        Some(diffs) => {
+            writeln!(writer, "balance differences: {}", diffs.len())?;
+            for diff in diffs {
+                writeln!(
+                    writer,
+                    "  client {} {} {}: expected {}, actual {}",
+                    diff.client, diff.currency, diff.field, diff.expected, diff.actual
+                )?;
+            }
+        }
+        None => {}
+    }
+    writer.flush()?;
+    Ok(())
+}